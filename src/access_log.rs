@@ -0,0 +1,121 @@
+use crate::{
+    anyhow::{Context, Result},
+    dev_server::{http_date, ParsedRequest},
+    serve_log as log,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Default format of the lines of the access log.
+pub(crate) const DEFAULT_FORMAT: &str = "{method} {path} {status} {bytes} {duration}";
+
+/// File receiving the lines of the access log.
+#[derive(Debug)]
+pub(crate) struct AccessLogFile(Mutex<fs::File>);
+
+impl AccessLogFile {
+    pub(crate) fn open(path: &Path) -> Result<AccessLogFile> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("could not open `{}`", path.display()))?;
+
+        Ok(AccessLogFile(Mutex::new(file)))
+    }
+}
+
+/// Writer recording the status and the size of the response going through it.
+pub(crate) struct Recorder<'a> {
+    inner: &'a mut dyn Write,
+    status_line: Vec<u8>,
+    bytes: u64,
+}
+
+impl<'a> Recorder<'a> {
+    pub(crate) fn new(inner: &'a mut dyn Write) -> Recorder<'a> {
+        Recorder {
+            inner,
+            status_line: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Status code of the response, if it has been written.
+    pub(crate) fn status(&self) -> Option<&str> {
+        std::str::from_utf8(&self.status_line)
+            .ok()?
+            .split_whitespace()
+            .nth(1)
+    }
+
+    /// Number of bytes of the response written so far.
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Write for Recorder<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if !self.status_line.ends_with(b"\r\n") && self.status_line.len() < 64 {
+            self.status_line.extend_from_slice(&buf[..written.min(64)]);
+            if let Some(end) = self.status_line.windows(2).position(|x| x == b"\r\n") {
+                self.status_line.truncate(end + 2);
+            }
+        }
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Log a request handled by the development server.
+///
+/// The status is `400` if the handler failed without writing a response as the
+/// server answers with it.
+pub(crate) fn log(
+    format: &str,
+    file: Option<&AccessLogFile>,
+    request: &ParsedRequest,
+    recorder: &Recorder,
+    failed: bool,
+    duration: Duration,
+) {
+    let status = match (recorder.status(), failed) {
+        (Some(status), _) => status,
+        (None, true) => "400",
+        (None, false) => "-",
+    };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+
+    let line = format
+        .replace("{method}", &request.method)
+        .replace("{path}", &request.path)
+        .replace("{target}", &request.target)
+        .replace("{status}", status)
+        .replace("{bytes}", &recorder.bytes.to_string())
+        .replace("{duration}", &format!("{:.1?}", duration))
+        .replace("{time}", &http_date(time))
+        .replace("{user_agent}", request.header("user-agent").unwrap_or("-"));
+
+    log::debug!("{}", line);
+
+    if let Some(AccessLogFile(file)) = file {
+        let mut file = file.lock().expect("access log lock is not poisoned");
+        if let Err(err) = writeln!(file, "{}", line) {
+            log::warn!("could not write the access log: {}", err);
+        }
+    }
+}
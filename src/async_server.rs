@@ -0,0 +1,745 @@
+use crate::{
+    anyhow::{bail, ensure, Context, Result},
+    dev_server::{
+        handle_request, host_allowed, is_shutting_down, reject_host, ConnectionGuard, DevServer,
+        Listener, ParsedRequest, TlsConfig,
+    },
+    live_reload::{self, LiveReload},
+    serve_log as log,
+};
+use hyper::{
+    body::HttpBody, header::HeaderMap, http::response, server::conn::Http, service::service_fn,
+    upgrade, Body, Method, Request, Response, Uri, Version,
+};
+use std::{
+    convert::Infallible,
+    io,
+    path::PathBuf,
+    sync::{atomic::AtomicUsize, Arc},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+};
+
+/// Number of writes of a response body buffered before the request handler
+/// waits for the connection.
+const BODY_CHANNEL_CAPACITY: usize = 16;
+
+/// Headers of the raw responses that are managed by hyper.
+const HOP_BY_HOP_HEADERS: [&str; 4] = ["connection", "keep-alive", "proxy-connection", "upgrade"];
+
+/// State shared by the connections.
+#[derive(Clone)]
+struct State {
+    server: Arc<DevServer>,
+    live_reload: Arc<LiveReload>,
+    served_path: Arc<PathBuf>,
+    connections: Arc<AtomicUsize>,
+    #[cfg(feature = "tls")]
+    tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+    /// Whether only the build notifications are answered, on the plain text
+    /// connections of the HTTPS port.
+    notifications_only: bool,
+}
+
+impl State {
+    /// Count a new connection, returns `None` if there are too many.
+    fn connection_guard(&self) -> Option<ConnectionGuard> {
+        let guard = ConnectionGuard::new(&self.connections);
+        if matches!(self.server.max_connections, Some(max) if guard.count > max) {
+            log::warn!("too many connections, closing the new one");
+            return None;
+        }
+        Some(guard)
+    }
+}
+
+/// Serve the connections using tokio and hyper.
+///
+/// The request handlers are blocking, they run on the blocking threads of the
+/// runtime and their raw response is converted to a hyper response.
+#[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+pub(crate) fn serve(
+    listeners: Vec<(Listener, Arc<PathBuf>)>,
+    server: Arc<DevServer>,
+    live_reload: Arc<LiveReload>,
+    tls_config: TlsConfig,
+) -> Result<()> {
+    ensure!(server.workers > 0, "at least one worker is required");
+
+    // NOTE: the request handlers run on the blocking threads
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .max_blocking_threads(server.workers)
+        .enable_all()
+        .build()
+        .context("could not start the async runtime")?;
+
+    let listeners = {
+        let _guard = runtime.enter();
+        listeners
+            .into_iter()
+            .map(|(listener, served_path)| Ok((AsyncListener::from_std(listener)?, served_path)))
+            .collect::<io::Result<Vec<_>>>()?
+    };
+
+    #[cfg(feature = "tls")]
+    let tls_acceptor = tls_config.map(|config| {
+        let mut config = (*config).clone();
+        if server.http2 {
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        }
+        tokio_rustls::TlsAcceptor::from(Arc::new(config))
+    });
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    runtime.block_on(async move {
+        let tasks: Vec<_> = listeners
+            .into_iter()
+            .map(|(listener, served_path)| {
+                let state = State {
+                    server: server.clone(),
+                    live_reload: live_reload.clone(),
+                    served_path,
+                    connections: connections.clone(),
+                    #[cfg(feature = "tls")]
+                    tls_acceptor: tls_acceptor.clone(),
+                    notifications_only: false,
+                };
+                (listener, state)
+            })
+            .map(|(listener, state)| match listener {
+                AsyncListener::Tcp(listener) => tokio::spawn(accept_connections(listener, state)),
+                #[cfg(unix)]
+                AsyncListener::Unix(listener) => {
+                    tokio::spawn(accept_unix_connections(listener, state))
+                }
+            })
+            .collect();
+        for task in tasks {
+            let _ = task.await;
+        }
+    });
+
+    Ok(())
+}
+
+/// A listener registered in the runtime.
+enum AsyncListener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl AsyncListener {
+    fn from_std(listener: Listener) -> io::Result<AsyncListener> {
+        match listener {
+            Listener::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                tokio::net::TcpListener::from_std(listener).map(AsyncListener::Tcp)
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                listener.set_nonblocking(true)?;
+                tokio::net::UnixListener::from_std(listener).map(AsyncListener::Unix)
+            }
+        }
+    }
+}
+
+/// Accept the connections of a listener, serving each one in its own task.
+async fn accept_connections(listener: tokio::net::TcpListener, state: State) {
+    loop {
+        let connection = listener.accept().await;
+        if is_shutting_down(&state.server) {
+            break;
+        }
+        let (stream, _) = match connection {
+            Ok(connection) => connection,
+            Err(err) => {
+                log::error!("an error occurred: {}", err);
+                continue;
+            }
+        };
+        let guard = match state.connection_guard() {
+            Some(guard) => guard,
+            None => continue,
+        };
+        #[cfg_attr(not(feature = "tls"), allow(unused_mut))]
+        let mut state = state.clone();
+
+        tokio::spawn(async move {
+            let _guard = guard;
+
+            // NOTE: like the threaded server, connections that don't start with
+            //       a TLS handshake are kept in plain text to notify the builds
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = state.tls_acceptor.clone() {
+                let mut first_byte = [0];
+                let peek = stream.peek(&mut first_byte);
+                let peeked = match state.server.header_timeout.or(state.server.timeout) {
+                    Some(timeout) => tokio::time::timeout(timeout, peek)
+                        .await
+                        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+                    None => peek.await,
+                };
+                if let Err(err) = peeked {
+                    log::debug!("connection closed: {}", err);
+                    return;
+                }
+                if first_byte[0] == crate::tls::HANDSHAKE_RECORD {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            let http2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+                            serve_connection(stream, state, http2).await
+                        }
+                        Err(err) => log::error!("an error occurred: {}", err),
+                    }
+                    return;
+                }
+
+                match (stream.peer_addr(), stream.local_addr()) {
+                    (Ok(peer), Ok(local)) if crate::tls::is_local(peer, local) => {
+                        state.notifications_only = true;
+                    }
+                    (peer, _) => {
+                        log::error!(
+                            "refused a plain text connection from {} on the HTTPS port",
+                            peer.map(|x| x.to_string()).unwrap_or_default()
+                        );
+                        return;
+                    }
+                }
+            }
+
+            serve_connection(stream, state, false).await;
+        });
+    }
+}
+
+/// Accept the connections of a Unix domain socket, always in plain text.
+#[cfg(unix)]
+async fn accept_unix_connections(listener: tokio::net::UnixListener, state: State) {
+    loop {
+        let connection = listener.accept().await;
+        if is_shutting_down(&state.server) {
+            break;
+        }
+        match connection {
+            Ok((stream, _)) => {
+                if let Some(guard) = state.connection_guard() {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        let _guard = guard;
+                        serve_connection(stream, state, false).await
+                    });
+                }
+            }
+            Err(err) => log::error!("an error occurred: {}", err),
+        }
+    }
+}
+
+/// Serve the requests of a connection, using HTTP/2 if it has been negotiated
+/// during the TLS handshake.
+async fn serve_connection<I>(io: I, state: State, http2: bool)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |request| respond(request, state.clone()));
+
+    let mut http = Http::new();
+    if http2 {
+        http.http2_only(true);
+    } else {
+        http.http1_only(true);
+    }
+
+    if let Err(err) = http.serve_connection(io, service).with_upgrades().await {
+        log::debug!("connection closed: {}", err);
+    }
+}
+
+async fn respond(mut request: Request<Body>, state: State) -> Result<Response<Body>, Infallible> {
+    let notification = state.server.reload
+        && *request.method() == Method::POST
+        && request.uri().path() == live_reload::RELOAD_PATH;
+    let head = parsed_request(
+        request.method(),
+        request.uri(),
+        request.version(),
+        request.headers(),
+    );
+
+    let response = if state.notifications_only && !notification {
+        log::error!("--> {} (400 BAD REQUEST)", request.uri().path());
+        Ok(Response::builder()
+            .status(400)
+            .body(Body::empty())
+            .expect("valid response"))
+    } else if !host_allowed(&state.server, &head) {
+        let mut raw_response = Vec::new();
+        reject_host(&mut raw_response, &head).and_then(|()| parse_response(&raw_response))
+    } else if state.server.reload && request.uri().path() == live_reload::RELOAD_PATH {
+        live_reload_response(&mut request, &state.live_reload).await
+    } else if state.server.reload && request.uri().path() == live_reload::EVENTS_PATH {
+        events_response(&state.live_reload)
+    } else if request.uri().path() == live_reload::STATUS_PATH {
+        let mut raw_response = Vec::new();
+        state
+            .live_reload
+            .write_status(&mut raw_response, &state.server.metrics)
+            .and_then(|()| parse_response(&raw_response))
+    } else {
+        handle(request, state).await
+    };
+
+    Ok(response.unwrap_or_else(|err| {
+        log::error!("an error occurred: {}", err);
+        Response::builder()
+            .status(400)
+            .body(Body::empty())
+            .expect("valid response")
+    }))
+}
+
+/// Run the request handlers and convert their raw response.
+async fn handle(request: Request<Body>, state: State) -> Result<Response<Body>> {
+    let (parts, body) = request.into_parts();
+    let mut parsed = parsed_request(&parts.method, &parts.uri, parts.version, &parts.headers);
+    parsed.body = match read_body(body, state.server.max_body_size).await? {
+        Some(body) => body,
+        None => {
+            log::error!("--> {} (413 PAYLOAD TOO LARGE)", parsed.path);
+            return Ok(Response::builder().status(413).body(Body::empty())?);
+        }
+    };
+
+    // NOTE: the body is streamed as the handlers write it, the head is sent
+    //       once it is complete
+    let (head_sender, head_receiver) = oneshot::channel();
+    let (body_sender, mut body_receiver) = mpsc::channel::<Vec<u8>>(BODY_CHANNEL_CAPACITY);
+    let handler = tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut writer = ResponseWriter {
+            head: Vec::new(),
+            head_sender: Some(head_sender),
+            decoder: None,
+            body_sender,
+        };
+        handle_request(&mut writer, &parsed, &state.server, &state.served_path)?;
+        ensure!(writer.head_sender.is_none(), "incomplete response");
+        Ok(())
+    });
+
+    let response = match head_receiver.await {
+        Ok(response) => response,
+        Err(_) => {
+            handler.await??;
+            bail!("incomplete response");
+        }
+    };
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        while let Some(data) = body_receiver.recv().await {
+            if sender.send_data(data.into()).await.is_err() {
+                break;
+            }
+        }
+        // NOTE: the handler stops writing once the connection is closed
+        drop(body_receiver);
+        let result = match handler.await {
+            Ok(result) => result,
+            Err(err) => Err(err.into()),
+        };
+        // NOTE: the response is incomplete, the client must not take it as is
+        if let Err(err) = result {
+            log::error!("an error occurred: {}", err);
+            sender.abort();
+        }
+    });
+
+    Ok(response.body(body)?)
+}
+
+/// Raw response written by the request handlers, whose head is sent once
+/// complete and whose body is streamed.
+struct ResponseWriter {
+    head: Vec<u8>,
+    head_sender: Option<oneshot::Sender<response::Builder>>,
+    /// Decoder of the body if it is sent with the chunked transfer encoding.
+    decoder: Option<ChunkedDecoder>,
+    body_sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl ResponseWriter {
+    fn send_body(&mut self, data: &[u8]) -> io::Result<()> {
+        let data = match &mut self.decoder {
+            Some(decoder) => decoder
+                .decode(data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?,
+            None => data.to_vec(),
+        };
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.body_sender
+            .blocking_send(data)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"))
+    }
+}
+
+impl io::Write for ResponseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.head_sender.is_none() {
+            self.send_body(buf)?;
+            return Ok(buf.len());
+        }
+
+        let searched = self.head.len().saturating_sub(3);
+        self.head.extend_from_slice(buf);
+        let head_end = match self.head[searched..]
+            .windows(4)
+            .position(|x| x == b"\r\n\r\n")
+        {
+            Some(position) => searched + position,
+            None => return Ok(buf.len()),
+        };
+
+        let (response, chunked) = parse_head(&self.head[..head_end])
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        if chunked {
+            self.decoder = Some(ChunkedDecoder::new());
+        }
+        let body = self.head.split_off(head_end + 4);
+        let head_sender = self.head_sender.take().expect("the head is not sent yet");
+        if head_sender.send(response).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "connection closed",
+            ));
+        }
+        self.send_body(&body)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Convert the head of a request to the request of the handlers.
+fn parsed_request(
+    method: &Method,
+    uri: &Uri,
+    version: Version,
+    headers: &HeaderMap,
+) -> ParsedRequest {
+    let target = uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+
+    let mut parsed = ParsedRequest::new(method.as_str(), target, &format!("{:?}", version));
+    parsed.headers = headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect();
+    // NOTE: HTTP/2 requests don't have a `Host` header
+    if let (false, Some(authority)) = (headers.contains_key("host"), uri.authority()) {
+        parsed
+            .headers
+            .push(("host".to_string(), authority.as_str().to_string()));
+    }
+
+    parsed
+}
+
+/// Answer the build notification or the WebSocket handshake of the live
+/// reload.
+async fn live_reload_response(
+    request: &mut Request<Body>,
+    live_reload: &Arc<LiveReload>,
+) -> Result<Response<Body>> {
+    if *request.method() == Method::POST {
+        let mut parsed = parsed_request(
+            request.method(),
+            request.uri(),
+            request.version(),
+            request.headers(),
+        );
+        if !live_reload.is_authorized(&parsed) {
+            log::warn!("rejected a build notification without the token of the server");
+            return Ok(Response::builder().status(403).body(Body::empty())?);
+        }
+
+        parsed.body = hyper::body::to_bytes(std::mem::take(request.body_mut()))
+            .await?
+            .to_vec();
+        live_reload.notified(&parsed);
+        return Ok(Response::builder().status(204).body(Body::empty())?);
+    }
+
+    let accept = live_reload::accept_key(
+        request
+            .headers()
+            .get("sec-websocket-key")
+            .and_then(|x| x.to_str().ok())
+            .context("missing WebSocket key")?,
+    );
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+    live_reload.add_client(Box::new(ChannelWriter(sender)));
+
+    let on_upgrade = upgrade::on(request);
+    tokio::spawn(async move {
+        let mut upgraded = match on_upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                log::error!("could not upgrade the connection: {}", err);
+                return;
+            }
+        };
+
+        while let Some(frame) = receiver.recv().await {
+            if upgraded.write_all(&frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .status(101)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", accept)
+        .body(Body::empty())?)
+}
+
+/// Start the server-sent events stream of the events endpoint.
+fn events_response(live_reload: &Arc<LiveReload>) -> Result<Response<Body>> {
+    if live_reload.event_clients_full() {
+        log::warn!("too many clients of the events endpoint, refusing the new one");
+        return Ok(Response::builder().status(503).body(Body::empty())?);
+    }
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+    live_reload.add_event_client(Box::new(ChannelWriter(sender)));
+
+    let (mut body_sender, body) = Body::channel();
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            if body_sender.send_data(event.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut response = parse_response(live_reload::EVENTS_RESPONSE_HEAD.as_bytes())?;
+    *response.body_mut() = body;
+    Ok(response)
+}
+
+/// Frames sent to a WebSocket served by a task of the runtime.
+struct ChannelWriter(mpsc::UnboundedSender<Vec<u8>>);
+
+impl io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "WebSocket closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Convert a raw HTTP/1.1 response written in full.
+fn parse_response(raw_response: &[u8]) -> Result<Response<Body>> {
+    let head_end = raw_response
+        .windows(4)
+        .position(|x| x == b"\r\n\r\n")
+        .context("incomplete response")?;
+    let (response, chunked) = parse_head(&raw_response[..head_end])?;
+
+    let body = &raw_response[head_end + 4..];
+    let body = if chunked {
+        let mut decoder = ChunkedDecoder::new();
+        let body = decoder.decode(body)?;
+        ensure!(decoder.is_done(), "truncated chunk");
+        body
+    } else {
+        body.to_vec()
+    };
+
+    Ok(response.body(Body::from(body))?)
+}
+
+/// Convert the head of a raw HTTP/1.1 response, without the empty line ending
+/// it, returning whether its body is sent with the chunked transfer encoding.
+fn parse_head(head: &[u8]) -> Result<(response::Builder, bool)> {
+    let head = std::str::from_utf8(head).context("invalid response")?;
+
+    let mut lines = head.split("\r\n");
+    let status: u16 = lines
+        .next()
+        .and_then(|x| x.split_whitespace().nth(1))
+        .and_then(|x| x.parse().ok())
+        .context("invalid status line")?;
+
+    let mut response = Response::builder().status(status);
+    let mut chunked = false;
+    for line in lines {
+        let (name, value) = line.split_once(':').context("invalid header")?;
+        let (name, value) = (name.trim(), value.trim());
+
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if !HOP_BY_HOP_HEADERS
+            .iter()
+            .any(|x| name.eq_ignore_ascii_case(x))
+        {
+            response = response.header(name, value);
+        }
+    }
+
+    Ok((response, chunked))
+}
+
+/// Read the body of a request, unless it is larger than `max_size` bytes.
+async fn read_body(mut body: Body, max_size: u64) -> Result<Option<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    while let Some(data) = body.data().await {
+        let data = data?;
+        if (bytes.len() + data.len()) as u64 > max_size {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&data);
+    }
+
+    Ok(Some(bytes))
+}
+
+/// Decoder of a body sent with the chunked transfer encoding, as it is
+/// written.
+struct ChunkedDecoder {
+    pending: Vec<u8>,
+    state: ChunkState,
+}
+
+enum ChunkState {
+    /// Waiting for the line with the size of the next chunk.
+    Size,
+    /// Bytes remaining in the current chunk.
+    Data(usize),
+    /// Waiting for the line break ending the current chunk.
+    DataEnd,
+    /// The last chunk has been received, the trailers are ignored.
+    Done,
+}
+
+impl ChunkedDecoder {
+    fn new() -> ChunkedDecoder {
+        ChunkedDecoder {
+            pending: Vec::new(),
+            state: ChunkState::Size,
+        }
+    }
+
+    /// Decode the next bytes of the body, returning the data of the chunks
+    /// they complete.
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.pending.extend_from_slice(data);
+
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let line_end = match self.pending.windows(2).position(|x| x == b"\r\n") {
+                        Some(line_end) => line_end,
+                        None => break,
+                    };
+                    let size = std::str::from_utf8(&self.pending[..line_end])?;
+                    let size = usize::from_str_radix(
+                        size.split(';').next().unwrap_or_default().trim(),
+                        16,
+                    )
+                    .context("invalid chunk size")?;
+                    self.pending.drain(..line_end + 2);
+                    self.state = match size {
+                        0 => ChunkState::Done,
+                        size => ChunkState::Data(size),
+                    };
+                }
+                ChunkState::Data(remaining) => {
+                    if self.pending.is_empty() {
+                        break;
+                    }
+                    let len = remaining.min(self.pending.len());
+                    body.extend(self.pending.drain(..len));
+                    self.state = match remaining - len {
+                        0 => ChunkState::DataEnd,
+                        remaining => ChunkState::Data(remaining),
+                    };
+                }
+                ChunkState::DataEnd => {
+                    if self.pending.len() < 2 {
+                        break;
+                    }
+                    ensure!(self.pending.starts_with(b"\r\n"), "invalid chunk");
+                    self.pending.drain(..2);
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Done => {
+                    self.pending.clear();
+                    break;
+                }
+            }
+        }
+
+        Ok(body)
+    }
+
+    /// Whether the last chunk has been received.
+    fn is_done(&self) -> bool {
+        matches!(self.state, ChunkState::Done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_chunked_body() {
+        let body = b"5\r\nhello\r\n6;name=value\r\n world\r\n0\r\n\r\n";
+
+        let mut decoder = ChunkedDecoder::new();
+        assert_eq!(decoder.decode(body).unwrap(), b"hello world");
+        assert!(decoder.is_done());
+
+        let mut decoder = ChunkedDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in body.iter() {
+            decoded.extend(decoder.decode(&[*byte]).unwrap());
+        }
+        assert_eq!(decoded, b"hello world");
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn decode_invalid_chunked_body() {
+        let mut decoder = ChunkedDecoder::new();
+        assert_eq!(decoder.decode(b"5\r\nhel").unwrap(), b"hel");
+        assert!(!decoder.is_done());
+
+        assert!(ChunkedDecoder::new().decode(b"x\r\n").is_err());
+        assert!(ChunkedDecoder::new().decode(b"2\r\nabcd").is_err());
+    }
+}
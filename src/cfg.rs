@@ -47,3 +47,33 @@ macro_rules! cfg_sass {
         )*
     }
 }
+
+macro_rules! cfg_brotli {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "brotli")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "brotli")))]
+            $item
+        )*
+    }
+}
+
+macro_rules! cfg_tls {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "tls")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+            $item
+        )*
+    }
+}
+
+macro_rules! cfg_async_server {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "async-server")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "async-server")))]
+            $item
+        )*
+    }
+}
@@ -0,0 +1,251 @@
+use crate::anyhow::{Context, Result};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+
+/// Configuration of the Content-Security-Policy generated by
+/// [`Dist::csp`](crate::Dist::csp).
+///
+/// The hashes of the inline scripts and styles of every HTML file are added to
+/// `script-src` and `style-src`, along with `'self'`. `'wasm-unsafe-eval'` is
+/// added to `script-src` only if the dist directory contains Wasm.
+///
+/// When the pages are served with [`DevServer::reload`](crate::DevServer::reload),
+/// the development server allows its live reload client in the policy: the
+/// hashes of its inline script and style and its WebSocket endpoint in
+/// `connect-src`. The files of the dist directory are not modified.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// # use xtask_wasm::{anyhow::Result, CspConfig, Dist};
+/// # fn main() -> Result<()> {
+/// Dist::default()
+///     .csp(CspConfig {
+///         connect_src: vec!["https://api.example.com".to_string()],
+///         ..Default::default()
+///     })
+///     .run("my-project")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CspConfig {
+    /// Sources of `default-src`, `'self'` by default.
+    pub default_src: Vec<String>,
+    /// Additional sources of `script-src`.
+    pub script_src: Vec<String>,
+    /// Additional sources of `style-src`.
+    pub style_src: Vec<String>,
+    /// Sources of `connect-src`.
+    pub connect_src: Vec<String>,
+    /// Sources of `img-src`.
+    pub img_src: Vec<String>,
+    /// Other directives, like `("frame-ancestors", vec!["'none'"])`.
+    pub directives: Vec<(String, Vec<String>)>,
+}
+
+impl Default for CspConfig {
+    fn default() -> CspConfig {
+        CspConfig {
+            default_src: vec!["'self'".to_string()],
+            script_src: Default::default(),
+            style_src: Default::default(),
+            connect_src: Default::default(),
+            img_src: Default::default(),
+            directives: Default::default(),
+        }
+    }
+}
+
+impl CspConfig {
+    /// Build the policy for an HTML document.
+    fn policy(&self, html: &str, has_wasm: bool) -> String {
+        let mut script_src = vec!["'self'".to_string()];
+        script_src.extend(self.script_src.iter().cloned());
+        script_src.extend(inline_hashes(html, "script"));
+        if has_wasm {
+            script_src.push("'wasm-unsafe-eval'".to_string());
+        }
+
+        let mut style_src = vec!["'self'".to_string()];
+        style_src.extend(self.style_src.iter().cloned());
+        style_src.extend(inline_hashes(html, "style"));
+
+        let mut directives = vec![
+            ("default-src", self.default_src.clone()),
+            ("script-src", script_src),
+            ("style-src", style_src),
+            ("connect-src", self.connect_src.clone()),
+            ("img-src", self.img_src.clone()),
+        ];
+        directives.extend(
+            self.directives
+                .iter()
+                .map(|(name, sources)| (name.as_str(), sources.clone())),
+        );
+
+        directives
+            .into_iter()
+            .filter(|(_, sources)| !sources.is_empty())
+            .map(|(name, sources)| format!("{} {}", name, sources.join(" ")))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Inject the Content-Security-Policy meta tag in the given HTML files.
+pub(crate) fn inject(config: &CspConfig, html_files: &[&Path], has_wasm: bool) -> Result<()> {
+    for file in html_files {
+        let mut html = fs::read_to_string(file)
+            .with_context(|| format!("could not read `{}`", file.display()))?;
+
+        let lowercase = html.to_ascii_lowercase();
+        let head_end = match lowercase
+            .find("<head>")
+            .or_else(|| lowercase.find("<head "))
+            .and_then(|x| html[x..].find('>').map(|y| x + y + 1))
+        {
+            Some(position) => position,
+            None => {
+                log::warn!("could not inject CSP: no <head> in {}", file.display());
+                continue;
+            }
+        };
+
+        let policy = config.policy(&html, has_wasm);
+        log::debug!("Content-Security-Policy of {}: {}", file.display(), policy);
+        html.insert_str(
+            head_end,
+            &format!(
+                "<meta http-equiv=\"Content-Security-Policy\" content=\"{}\">",
+                policy.replace('"', "&quot;")
+            ),
+        );
+
+        fs::write(file, html).with_context(|| format!("could not write `{}`", file.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Hashes of the content of the inline `element`s (without `src` attribute).
+fn inline_hashes(html: &str, element: &str) -> Vec<String> {
+    let lowercase = html.to_ascii_lowercase();
+    let opening_tag = format!("<{}", element);
+    let closing_tag = format!("</{}", element);
+    let mut hashes = Vec::new();
+    let mut position = 0;
+
+    while let Some(start) = lowercase[position..].find(&opening_tag) {
+        let start = position + start;
+        let content_start = match lowercase[start..].find('>') {
+            Some(x) => start + x + 1,
+            None => break,
+        };
+        let content_end = match lowercase[content_start..].find(&closing_tag) {
+            Some(x) => content_start + x,
+            None => break,
+        };
+        position = content_end;
+
+        if lowercase[start..content_start].contains(" src=") {
+            continue;
+        }
+
+        hashes.push(hash_source(&html.as_bytes()[content_start..content_end]));
+    }
+
+    hashes
+}
+
+/// Source expression allowing an inline element with the given content.
+fn hash_source(content: &[u8]) -> String {
+    format!(
+        "'sha256-{}'",
+        base64::engine::general_purpose::STANDARD.encode(Sha256::digest(content))
+    )
+}
+
+/// Allow the inline script and style of the live reload client and its
+/// WebSocket endpoint in the Content-Security-Policy meta tag of a document.
+pub(crate) fn allow_live_reload_client(
+    html: &mut String,
+    script: &str,
+    style: &str,
+    host: Option<&str>,
+) {
+    let lowercase = html.to_ascii_lowercase();
+    let meta = match lowercase.find("http-equiv=\"content-security-policy\"") {
+        Some(x) => x,
+        None => return,
+    };
+    let tag_start = lowercase[..meta].rfind('<').unwrap_or(meta);
+    let tag_end = match lowercase[meta..].find('>') {
+        Some(x) => meta + x,
+        None => return,
+    };
+    let (start, end) = match lowercase[tag_start..tag_end].find("content=\"") {
+        Some(x) => {
+            let start = tag_start + x + "content=\"".len();
+            match lowercase[start..tag_end].find('"') {
+                Some(y) => (start, start + y),
+                None => return,
+            }
+        }
+        None => return,
+    };
+
+    let websocket = match host {
+        Some(host) => vec![format!("ws://{}", host), format!("wss://{}", host)],
+        None => vec!["ws:".to_string(), "wss:".to_string()],
+    };
+    let policy = add_sources(
+        &html[start..end],
+        &[
+            ("script-src", vec![hash_source(script.as_bytes())]),
+            ("style-src", vec![hash_source(style.as_bytes())]),
+            ("connect-src", websocket),
+        ],
+    );
+    html.replace_range(start..end, &policy);
+}
+
+/// Add sources to the directives of a policy. A missing directive is created
+/// from `default-src`, unless the policy has none.
+fn add_sources(policy: &str, additions: &[(&str, Vec<String>)]) -> String {
+    let mut directives: Vec<(String, Vec<String>)> = policy
+        .split(';')
+        .filter_map(|directive| {
+            let mut parts = directive.split_whitespace();
+            let name = parts.next()?.to_ascii_lowercase();
+            Some((name, parts.map(ToString::to_string).collect()))
+        })
+        .collect();
+    let default_src = directives
+        .iter()
+        .find(|(name, _)| name == "default-src")
+        .map(|(_, sources)| sources.clone());
+
+    for (name, sources) in additions {
+        let existing = match directives.iter_mut().position(|(x, _)| x == name) {
+            Some(index) => &mut directives[index].1,
+            None => match &default_src {
+                Some(default_src) => {
+                    directives.push((name.to_string(), default_src.clone()));
+                    &mut directives.last_mut().expect("directive was pushed").1
+                }
+                None => continue,
+            },
+        };
+        // NOTE: `'none'` can't be combined with other sources
+        existing.retain(|x| x != "'none'");
+        existing.extend(sources.iter().cloned());
+    }
+
+    directives
+        .into_iter()
+        .map(|(name, sources)| format!("{} {}", name, sources.join(" ")))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
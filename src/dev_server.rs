@@ -1,14 +1,28 @@
 use crate::{
-    anyhow::{bail, Context, Result},
+    access_log,
+    anyhow::{bail, ensure, Context, Result},
     camino::Utf8Path,
-    clap, Watch,
+    clap, live_reload, metrics, mock_api, serve_log as log,
+    watcher::{
+        self, OnResult, RunWith, Step, WatchContext, WatchEventKind, WatchFilter, WatchHandle,
+        WatchResult, WatchTrigger, Watcher,
+    },
+    Watch,
 };
 use std::{
-    ffi, fs,
-    io::{prelude::*, BufReader},
-    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
-    path::{Path, PathBuf},
+    borrow::Cow,
+    collections::HashMap,
+    ffi, fmt, fs,
+    io::{self, prelude::*, BufReader},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Component, Path, PathBuf},
     process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
 /// A simple HTTP server useful during development.
@@ -53,6 +67,33 @@ use std::{
 /// Add a `start` subcommand that will run `cargo xtask dist`, watching for
 /// changes in the workspace and serve the files in the default dist directory
 /// (`target/debug/dist` for non-release) at a given IP address.
+///
+/// # Status
+///
+/// The server answers `/__xtask/status` with a JSON object containing the
+/// result of the last build (`success`, `error`, `duration` in seconds and
+/// `timestamp`), known when the command is run with [`Self::reload`], the
+/// number of served `files` and the `uptime` of the server in seconds.
+///
+/// The `requests` field summarizes the handled requests: their `count`, the
+/// `bytes` of their responses and their total `duration` in seconds, followed
+/// by the `paths` that took the longest to serve. The same summary is logged
+/// when the server stops, for instance with [`DevServerHandle::shutdown`].
+///
+/// # Events
+///
+/// With [`Self::reload`], `/__xtask/events` streams the builds of the command
+/// as [server-sent events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events):
+/// `build-started`, `build-finished` with the `duration` of the build and
+/// `build-failed` with its `duration` and `error`. Up to 64 clients can be
+/// connected at the same time, the next ones get a `503 Service Unavailable`.
+///
+/// ```js
+/// const events = new EventSource("/__xtask/events");
+/// events.addEventListener("build-failed", (event) => {
+///     console.error(JSON.parse(event.data).error);
+/// });
+/// ```
 #[non_exhaustive]
 #[derive(Debug, clap::Parser)]
 #[clap(
@@ -64,9 +105,52 @@ pub struct DevServer {
     /// IP address to bind. Default to `127.0.0.1`.
     #[clap(long, default_value = "127.0.0.1")]
     pub ip: IpAddr,
-    /// Port number. Default to `8000`.
+    /// Port number. Default to `8000`, `0` picks any free port.
     #[clap(long, default_value = "8000")]
     pub port: u16,
+    /// Use the next free port if the port is already in use.
+    #[clap(long)]
+    pub auto_port: bool,
+    /// Listen on both IPv4 and IPv6.
+    #[clap(long)]
+    pub dual_stack: bool,
+    /// Path of a Unix domain socket to listen on instead of the IP address.
+    #[clap(long)]
+    pub unix_socket: Option<PathBuf>,
+    /// Open the browser once the server is started.
+    #[clap(long)]
+    pub open: bool,
+    /// Path opened in the browser. Default to `/`.
+    #[clap(long, default_value = "/")]
+    pub open_path: String,
+    /// Number of threads handling the connections. Default to `32`.
+    #[clap(long, default_value = "32")]
+    pub workers: usize,
+    /// Maximum number of open connections, the others are closed right away.
+    #[clap(long)]
+    pub max_connections: Option<usize>,
+    /// Time after which a connection is closed if reading the request or
+    /// writing the response blocks.
+    #[clap(skip)]
+    pub timeout: Option<Duration>,
+    /// Time after which an idle keep-alive connection is closed. Default to
+    /// 5 seconds.
+    #[clap(skip = Some(Duration::from_secs(5)))]
+    pub keep_alive_timeout: Option<Duration>,
+    /// Time given to the clients to send the head of a request. Default to 10
+    /// seconds.
+    #[clap(skip = Some(Duration::from_secs(10)))]
+    pub header_timeout: Option<Duration>,
+    /// Maximum size of the head of a request, in bytes. Default to `16384`.
+    #[clap(long, default_value = "16384")]
+    pub max_header_size: usize,
+    /// Maximum size of the body of a request, in bytes. Default to `10485760`.
+    #[clap(long, default_value = "10485760")]
+    pub max_body_size: u64,
+    /// Host name announced over mDNS, like `my-app.local`.
+    #[cfg(feature = "mdns")]
+    #[clap(long)]
+    pub mdns: Option<String>,
 
     /// Watch object for detecting changes.
     ///
@@ -80,9 +164,247 @@ pub struct DevServer {
     #[clap(skip)]
     pub command: Option<process::Command>,
 
+    /// Commands executed in order after the command succeeded.
+    #[clap(skip)]
+    pub then_commands: Vec<process::Command>,
+
+    #[clap(skip)]
+    run_with: Option<RunWith>,
+
+    /// Commands executed instead of the command when the changed paths match
+    /// their glob pattern.
+    #[clap(skip)]
+    pub pattern_commands: Vec<(String, process::Command)>,
+
+    /// Commands executed at the same time as the command.
+    #[clap(skip)]
+    pub parallel_commands: Vec<process::Command>,
+
+    /// Glob patterns of the paths whose changes execute the command, relative
+    /// to the workspace root.
+    #[clap(long = "watch-glob")]
+    pub watch_globs: Vec<String>,
+
+    /// Glob patterns of the paths whose changes are ignored, relative to the
+    /// workspace root.
+    #[clap(long = "ignore-glob")]
+    pub exclude_globs: Vec<String>,
+
+    /// Ignore the changes of the paths listed in the `.gitignore` and
+    /// `.ignore` files of the workspace, unless `--no-gitignore` is given.
+    #[clap(long = "no-gitignore", action = clap::ArgAction::SetFalse)]
+    pub use_gitignore: bool,
+
+    /// Watch the hidden files and directories, whose name starts with a dot,
+    /// except `.git`.
+    #[clap(long)]
+    pub watch_hidden: bool,
+
+    /// Extensions of the files whose changes execute the command.
+    #[clap(long = "watch-extension")]
+    pub watch_extensions: Vec<String>,
+
+    #[clap(skip)]
+    watch_filters: Vec<WatchFilter>,
+
+    /// Kinds of changes that execute the command. Default to the changes of
+    /// the content of the files.
+    #[clap(skip = watcher::default_event_kinds())]
+    pub watch_event_kinds: Vec<WatchEventKind>,
+
+    /// Clear the terminal before executing the command again.
+    #[clap(long = "clear")]
+    pub clear_terminal: bool,
+
+    /// Read the keys typed in the terminal: `r` runs the command again, `p`
+    /// pauses or resumes watching and `q` quits.
+    #[clap(long)]
+    pub interactive: bool,
+
+    #[clap(skip)]
+    on_result: Vec<OnResult>,
+
+    /// Show a desktop notification after each build.
+    #[clap(long)]
+    pub desktop_notifications: bool,
+
+    /// Stop the server after the given number of runs of the command.
+    #[clap(long)]
+    pub max_runs: Option<usize>,
+
+    /// Stop the server once the command succeeded.
+    #[clap(long)]
+    pub exit_on_success: bool,
+
+    /// Execute the command again when it exits, waiting longer after each
+    /// failure in a row.
+    #[clap(long)]
+    pub restart_on_exit: bool,
+
+    /// Environment file whose variables are given to the command, read again
+    /// before each run.
+    #[clap(long)]
+    pub env_file: Option<PathBuf>,
+
+    #[clap(skip)]
+    change_senders: Vec<mpsc::Sender<WatchContext>>,
+
+    #[clap(skip)]
+    trigger: WatchTrigger,
+
     /// Use another file path when the URL is not found.
     #[clap(skip)]
     pub not_found_path: Option<PathBuf>,
+
+    /// HTML pages of the error responses, by status code.
+    #[clap(skip)]
+    pub error_pages: Vec<(u16, PathBuf)>,
+
+    /// Serve the index file of the served path for the navigations to unknown
+    /// paths.
+    #[clap(long)]
+    pub spa: bool,
+
+    /// Serve `<path>.html` for the requested paths without extension.
+    #[clap(long)]
+    pub clean_urls: bool,
+
+    /// Directories served under a path prefix, in addition to the served
+    /// path.
+    #[clap(skip)]
+    pub mounts: Vec<(String, PathBuf)>,
+
+    /// Directories served on other ports, in addition to the served path.
+    #[clap(skip)]
+    pub secondary_ports: Vec<(u16, PathBuf)>,
+
+    /// Path prefixes forwarded to a backend URL.
+    #[clap(skip)]
+    pub proxies: Vec<(String, String)>,
+
+    /// Paths redirected to another URL, with the status of the redirection.
+    #[clap(skip)]
+    pub redirects: Vec<(String, String, u16)>,
+
+    /// Path prefixes replaced by another one before serving the requests.
+    #[clap(skip)]
+    pub rewrites: Vec<(String, String)>,
+
+    /// Directory of JSON or YAML fixtures answering the API requests.
+    #[clap(long)]
+    pub mock_api: Option<PathBuf>,
+
+    #[clap(skip)]
+    middlewares: Vec<Middleware>,
+
+    #[clap(skip)]
+    routes: Vec<Route>,
+
+    /// Headers added to every response.
+    #[clap(skip)]
+    pub headers: Vec<(String, String)>,
+
+    /// Content types of file extensions, overriding the built-in ones.
+    #[clap(skip)]
+    pub mime_types: Vec<(String, String)>,
+
+    /// `Cache-Control` header of the served files. Default to `no-cache`.
+    #[clap(long, default_value = "no-cache")]
+    pub cache_control: String,
+
+    /// `Cache-Control` header of the files matching a pattern, overriding
+    /// [`Self::cache_control`].
+    #[clap(skip)]
+    pub cache_control_rules: Vec<(String, String)>,
+
+    /// Send the `Cross-Origin-Opener-Policy` and `Cross-Origin-Embedder-Policy`
+    /// headers.
+    #[clap(long)]
+    pub cross_origin_isolation: bool,
+
+    /// Host names accepted in the `Host` header, in addition to `localhost`
+    /// and IP addresses. A leading dot matches the subdomains.
+    #[clap(long = "allowed-host")]
+    pub allowed_hosts: Vec<String>,
+
+    /// Accept any `Host` header.
+    #[clap(long)]
+    pub disable_host_check: bool,
+
+    /// Reload the browsers when the command finishes successfully.
+    #[clap(long)]
+    pub reload: bool,
+
+    /// Run the command once before starting to serve.
+    #[clap(long)]
+    pub wait_for_build: bool,
+
+    /// Prefix the output of the command with `[build]`.
+    #[clap(long)]
+    pub prefix_output: bool,
+
+    /// File names served for the requests of a directory, in order of
+    /// preference.
+    #[clap(long = "index-file", default_values = ["index.html", "index.htm"])]
+    pub index_files: Vec<String>,
+
+    /// List the content of the directories without index.
+    #[clap(long)]
+    pub directory_listing: bool,
+
+    /// Serve the files whose canonical path is outside of the served
+    /// directories, through symbolic links.
+    #[clap(long)]
+    pub follow_symlinks: bool,
+
+    /// Keep the served files in memory until the served directory changes.
+    #[clap(long)]
+    pub cache_files: bool,
+
+    /// File where the requests are logged.
+    #[clap(long)]
+    pub access_log: Option<PathBuf>,
+
+    /// Format of the logged requests. The placeholders are `{method}`,
+    /// `{path}`, `{target}`, `{status}`, `{bytes}`, `{duration}`, `{time}`
+    /// and `{user_agent}`.
+    #[clap(long, default_value = access_log::DEFAULT_FORMAT)]
+    pub access_log_format: String,
+
+    #[clap(skip)]
+    access_log_file: Option<Arc<access_log::AccessLogFile>>,
+
+    /// Simulated network conditions of the responses.
+    #[clap(skip)]
+    pub throttle: Option<ThrottleConfig>,
+
+    /// Serve over HTTPS using a self-signed certificate.
+    #[cfg(feature = "tls")]
+    #[clap(long)]
+    pub tls: bool,
+
+    /// Paths of the PEM encoded certificate chain and private key used to
+    /// serve over HTTPS.
+    #[cfg(feature = "tls")]
+    #[clap(skip)]
+    pub tls_certificate: Option<(PathBuf, PathBuf)>,
+
+    /// Offer HTTP/2 to the browsers connecting over HTTPS.
+    #[cfg(all(feature = "async-server", feature = "tls"))]
+    #[clap(long)]
+    pub http2: bool,
+
+    #[clap(skip)]
+    shutdown: Arc<AtomicBool>,
+
+    #[clap(skip)]
+    file_cache: Option<Arc<FileCache>>,
+
+    #[clap(skip)]
+    notify_token: String,
+
+    #[clap(skip)]
+    pub(crate) metrics: Arc<metrics::Metrics>,
 }
 
 impl DevServer {
@@ -94,12 +416,269 @@ impl DevServer {
         self
     }
 
+    /// Use the next free port if the port is already in use.
+    ///
+    /// The port actually used is logged when the server starts.
+    pub fn auto_port(mut self, res: bool) -> Self {
+        self.auto_port = res;
+        self
+    }
+
+    /// Listen on both IPv4 and IPv6.
+    ///
+    /// With an unspecified address, like `0.0.0.0` or `::`, the server listens
+    /// on every address of both stacks. With a loopback address, it listens on
+    /// both `127.0.0.1` and `::1`.
+    pub fn dual_stack(mut self, res: bool) -> Self {
+        self.dual_stack = res;
+        self
+    }
+
+    /// Listen on a Unix domain socket instead of the IP address.
+    ///
+    /// Useful when the server is behind another proxy, in a container for
+    /// example. An existing socket file at the path is replaced. Only
+    /// available on Unix platforms, HTTPS is not supported on the socket.
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.unix_socket = Some(path.into());
+        self
+    }
+
+    /// Open the system's browser at the served URL once the server is started.
+    pub fn open(mut self, res: bool) -> Self {
+        self.open = res;
+        self
+    }
+
+    /// Open the system's browser at the given path of the served URL once the
+    /// server is started.
+    pub fn open_path(mut self, path: impl Into<String>) -> Self {
+        self.open = true;
+        self.open_path = path.into();
+        self
+    }
+
+    /// Set the number of threads handling the connections.
+    ///
+    /// The connections that come in while every thread is busy wait for one to
+    /// be available.
+    pub fn workers(mut self, n: usize) -> Self {
+        self.workers = n;
+        self
+    }
+
+    /// Limit the number of open connections.
+    ///
+    /// The connections that come in while the limit is reached are closed
+    /// right away, including the idle keep-alive connections and the ones
+    /// waiting for a worker.
+    pub fn max_connections(mut self, n: usize) -> Self {
+        self.max_connections = Some(n);
+        self
+    }
+
+    /// Close the connections that block for longer than `timeout` while
+    /// reading the request or writing the response.
+    ///
+    /// This prevents a stuck client from holding a worker indefinitely. It
+    /// only applies to the default threaded server.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Close the keep-alive connections that don't send a new request within
+    /// `timeout`, `None` keeps them open. Default to 5 seconds.
+    ///
+    /// It only applies to the default threaded server.
+    pub fn keep_alive_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.keep_alive_timeout = timeout.into();
+        self
+    }
+
+    /// Close the connections that take longer than `timeout` to send the
+    /// request line and the headers of a request, `None` waits indefinitely.
+    /// Default to 10 seconds.
+    ///
+    /// Unlike [`Self::timeout`], a client can't extend it by sending the head
+    /// of the request one byte at a time. It only applies to the default
+    /// threaded server.
+    pub fn header_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.header_timeout = timeout.into();
+        self
+    }
+
+    /// Answer the requests whose request line and headers are larger than
+    /// `size` bytes with `431 Request Header Fields Too Large`. Default to
+    /// 16 KiB.
+    ///
+    /// It only applies to the default threaded server.
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.max_header_size = size;
+        self
+    }
+
+    /// Answer the requests whose body is larger than `size` bytes with
+    /// `413 Payload Too Large`. Default to 10 MiB.
+    ///
+    /// The default threaded server only reads the bodies with a
+    /// `Content-Length`, the requests sent with a `Transfer-Encoding` are
+    /// answered with `411 Length Required`.
+    pub fn max_body_size(mut self, size: u64) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Announce the server over mDNS with the given host name.
+    ///
+    /// The server can then be reached from the other devices of the local
+    /// network at `http://<name>.local:<port>`, whatever the IP address of the
+    /// machine. This requires binding to an address reachable from the
+    /// network, like `0.0.0.0`. The announced host name is accepted by the
+    /// host check, see [`Self::allowed_host`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::net::{IpAddr, Ipv4Addr};
+    /// # use xtask_wasm::DevServer;
+    /// let dev_server = DevServer::default()
+    ///     .address(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8000)
+    ///     .mdns("my-app");
+    /// ```
+    #[cfg(feature = "mdns")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "mdns")))]
+    pub fn mdns(mut self, name: impl Into<String>) -> Self {
+        self.mdns = Some(name.into());
+        self
+    }
+
     /// Set the command that is executed when a change is detected.
+    ///
+    /// The paths that changed since the previous run are given to the command
+    /// in the `XTASK_WATCH_CHANGED` environment variable, separated like the
+    /// `PATH` (`:` on Unix, `;` on Windows). The variable is not set for the
+    /// first run.
+    ///
+    /// When the command fails several times in a row, the next runs are
+    /// delayed, doubling from 1 second up to 30 seconds, in case the changes
+    /// are made by the command itself.
+    ///
+    /// The processes started by the command, like a server started by a
+    /// script, are killed with it when a change is detected and when the
+    /// server stops. On Unix, the command runs in its own process group and
+    /// can't read the terminal, the signals stopping the xtask being
+    /// forwarded to it.
+    ///
+    /// The served directories, including the ones of
+    /// [`Self::secondary_port`], and the `target` directories of the workspace
+    /// members are not watched, so that the outputs of the builds don't
+    /// trigger new ones. A [`Dist::dist_dir_path`](crate::Dist::dist_dir_path)
+    /// that is not served must be excluded from the [`Self::watch`] instead.
+    ///
+    /// The watched and ignored paths can be shared in the workspace manifest,
+    /// relative to the workspace root, in addition to the ones given on the
+    /// command line. The `debounce` is in milliseconds.
+    ///
+    /// ```toml
+    /// [workspace.metadata.xtask-wasm.watch]
+    /// watch = ["crates", "assets"]
+    /// ignore = ["docs"]
+    /// debounce = 500
+    /// ```
     pub fn command(mut self, command: process::Command) -> Self {
         self.command = Some(command);
         self
     }
 
+    /// Execute another command after the previous ones succeeded, on each
+    /// change. The next commands are not executed once one of them fails.
+    ///
+    /// This sets the command if there is none yet.
+    ///
+    /// ```rust,no_run
+    /// # use std::process;
+    /// # use xtask_wasm::DevServer;
+    /// let mut check = process::Command::new("cargo");
+    /// check.args(["fmt", "--check"]);
+    /// DevServer::default().command(check).then(xtask_wasm::xtask_command());
+    /// ```
+    pub fn then(mut self, command: process::Command) -> Self {
+        match self.command {
+            Some(_) => self.then_commands.push(command),
+            None => self.command = Some(command),
+        }
+        self
+    }
+
+    /// Call a function when a change is detected, before executing the
+    /// commands, which are not executed if it fails.
+    ///
+    /// The function is given the changed paths and the number of the run.
+    /// This allows building in the process of the xtask, for instance with
+    /// [`Dist::run`](crate::Dist::run), instead of running
+    /// `cargo xtask dist` which builds the xtask again on every change. The
+    /// function runs on another thread and can't be interrupted: when the
+    /// files change again, the next run starts once it returned.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::{DevServer, Dist};
+    /// DevServer::default().run_with(|context| {
+    ///     log::info!("Build #{}", context.iteration);
+    ///     Dist::default().run("app")?;
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn run_with(
+        mut self,
+        function: impl Fn(&WatchContext) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.run_with = Some(RunWith(Arc::new(function)));
+        self
+    }
+
+    /// Execute a command instead of the command when the changed paths match a
+    /// glob pattern, relative to the workspace root, see
+    /// [`Self::watch_glob`].
+    ///
+    /// This avoids building the whole application when only the stylesheets
+    /// changed for instance. This can be called multiple times, each changed
+    /// path executes the command of the first pattern it matches, or the
+    /// command and the function of [`Self::run_with`] when it matches none.
+    ///
+    /// ```rust,no_run
+    /// # use std::process;
+    /// # use xtask_wasm::DevServer;
+    /// let mut sass = process::Command::new("sass");
+    /// sass.args(["styles/main.scss", "target/debug/dist/main.css"]);
+    /// DevServer::default()
+    ///     .arg("dist")
+    ///     .command_for("**/*.scss", sass);
+    /// ```
+    pub fn command_for(mut self, pattern: impl Into<String>, command: process::Command) -> Self {
+        self.pattern_commands.push((pattern.into(), command));
+        self
+    }
+
+    /// Execute commands at the same time as the command on each change, like
+    /// the tests while building the application.
+    ///
+    /// The commands are killed and executed again on the next change, like
+    /// the command. The run only succeeds once all of them succeeded, a
+    /// failure doesn't stop the others.
+    ///
+    /// ```rust,no_run
+    /// # use std::process;
+    /// # use xtask_wasm::DevServer;
+    /// let mut test = process::Command::new("cargo");
+    /// test.arg("test");
+    /// DevServer::default().arg("dist").run_parallel([test]);
+    /// ```
+    pub fn run_parallel(mut self, commands: impl IntoIterator<Item = process::Command>) -> Self {
+        self.parallel_commands.extend(commands);
+        self
+    }
+
     /// Adds an argument to pass to the command executed when changes are
     /// detected.
     ///
@@ -122,163 +701,3890 @@ impl DevServer {
         self
     }
 
-    /// Use another file path when the URL is not found.
-    pub fn not_found(mut self, path: impl Into<PathBuf>) -> Self {
-        self.not_found_path.replace(path.into());
+    /// Only execute the command when the changed paths match a glob pattern,
+    /// relative to the workspace root.
+    ///
+    /// `*` matches any sequence of characters within a path segment and `**`
+    /// any number of segments. A pattern without `/` matches the file names
+    /// at any depth and the files of a matching directory match too. This
+    /// can be called multiple times, a path matching any pattern is watched.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default()
+    ///     .watch_glob("crates/*/src/**")
+    ///     .watch_glob("assets");
+    /// ```
+    pub fn watch_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.watch_globs.push(pattern.into());
         self
     }
 
-    /// Start the server, serving the files at `served_path`.
+    /// Ignore the changes of the paths matching a glob pattern, relative to
+    /// the workspace root, see [`Self::watch_glob`].
     ///
-    /// [`crate::default_dist_dir`] should be used to get the dist directory
-    /// that needs to be served.
-    pub fn start(self, served_path: impl AsRef<Path>) -> Result<()> {
-        let watch_process = if let Some(command) = self.command {
-            // NOTE: the path needs to exists in order to be excluded because it is canonicalize
-            let _ = std::fs::create_dir_all(&served_path);
-            let watch = self.watch.exclude_path(&served_path);
-            let handle = std::thread::spawn(|| match watch.run(command) {
-                Ok(()) => log::trace!("Starting to watch"),
-                Err(err) => log::error!("an error occurred when starting to watch: {}", err),
-            });
-
-            Some(handle)
-        } else {
-            None
-        };
-
-        serve(
-            self.ip,
-            self.port,
-            served_path,
-            self.not_found_path.as_deref(),
-        )
-        .context("an error occurred when starting to serve")?;
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().exclude_glob("**/*.md");
+    /// ```
+    pub fn exclude_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude_globs.push(pattern.into());
+        self
+    }
 
-        if let Some(handle) = watch_process {
-            handle.join().expect("an error occurred when exiting watch");
-        }
+    /// Ignore the changes of the paths listed in the `.gitignore` and
+    /// `.ignore` files of the workspace, like the generated files or
+    /// `node_modules`. Default to `true`.
+    ///
+    /// The ignore files of every directory from the workspace root to the
+    /// changed path are used, the rules of `.ignore` taking precedence.
+    pub fn use_gitignore(mut self, res: bool) -> Self {
+        self.use_gitignore = res;
+        self
+    }
 
-        Ok(())
+    /// Watch the hidden files and directories, whose name starts with a dot,
+    /// like `.env`. Default to `false`.
+    ///
+    /// The `.git` directory is never watched.
+    pub fn watch_hidden(mut self, res: bool) -> Self {
+        self.watch_hidden = res;
+        self
     }
 
-    fn set_xtask_command(&mut self) -> &mut process::Command {
-        if self.command.is_none() {
-            self.command = Some(crate::xtask_command());
-        }
-        self.command.as_mut().unwrap()
+    /// Only execute the command when files with one of the given extensions
+    /// change, ignoring the other files like logs or databases.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().watch_extensions(["rs", "toml", "scss", "html"]);
+    /// ```
+    pub fn watch_extensions(
+        mut self,
+        extensions: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.watch_extensions = extensions.into_iter().map(Into::into).collect();
+        self
     }
-}
 
-impl Default for DevServer {
-    fn default() -> DevServer {
-        DevServer {
-            ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-            port: 8000,
-            watch: Default::default(),
-            command: None,
-            not_found_path: None,
-        }
+    /// Only execute the command when the function returns `true` for a
+    /// changed path, for the exclusions the glob patterns can't express.
+    ///
+    /// The function is given the absolute path, once the other filters
+    /// accepted it. This can be called multiple times, a path being watched
+    /// if all the functions accept it.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().watch_filter(|path| {
+    ///     !path.to_string_lossy().contains(".generated.")
+    /// });
+    /// ```
+    pub fn watch_filter(mut self, filter: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.watch_filters.push(WatchFilter(Arc::new(filter)));
+        self
     }
-}
 
-fn serve(
-    ip: IpAddr,
-    port: u16,
-    served_path: impl AsRef<Path>,
-    not_found_path: Option<impl AsRef<Path>>,
-) -> Result<()> {
-    let address = SocketAddr::new(ip, port);
-    let listener = TcpListener::bind(address).context("cannot bind to the given address")?;
+    /// Set the kinds of changes that execute the command.
+    ///
+    /// Default to the creations, modifications, renames and removals of
+    /// files. The changes of the metadata only, like the permissions or the
+    /// modification time, and the accesses are ignored as they cause spurious
+    /// builds on some platforms.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::{DevServer, WatchEventKind};
+    /// DevServer::default().watch_event_kinds([WatchEventKind::Create, WatchEventKind::Modify]);
+    /// ```
+    pub fn watch_event_kinds(mut self, kinds: impl IntoIterator<Item = WatchEventKind>) -> Self {
+        self.watch_event_kinds = kinds.into_iter().collect();
+        self
+    }
 
-    log::info!("Development server running at: http://{}", &address);
+    /// Clear the terminal before executing the command again after a change,
+    /// so the output of each build starts fresh, like `cargo watch -c`.
+    ///
+    /// The messages of the server printed before are cleared too.
+    pub fn clear_terminal(mut self, res: bool) -> Self {
+        self.clear_terminal = res;
+        self
+    }
 
-    for mut stream in listener.incoming().filter_map(|x| x.ok()) {
-        respond_to_request(&mut stream, &served_path, not_found_path.as_ref()).unwrap_or_else(
-            |e| {
-                let _ = stream.write("HTTP/1.1 400 BAD REQUEST\r\n\r\n".as_bytes());
-                log::error!("an error occurred: {}", e);
-            },
-        );
+    /// Read the keys typed in the terminal, followed by Enter, while watching:
+    /// `r` runs the command again, `p` pauses or resumes watching and `q`
+    /// stops the command and the server.
+    ///
+    /// The commands don't receive the input of the terminal.
+    pub fn interactive(mut self, res: bool) -> Self {
+        self.interactive = res;
+        self
     }
 
-    Ok(())
-}
+    /// Call a function after each run of the command, with its result.
+    ///
+    /// The runs interrupted by a change are not reported. The function is
+    /// called by the watch process and should return quickly. This can be
+    /// called multiple times.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().on_result(|result| {
+    ///     if !result.success {
+    ///         eprintln!("\x07");
+    ///     }
+    /// });
+    /// ```
+    pub fn on_result(mut self, function: impl Fn(&WatchResult) + Send + Sync + 'static) -> Self {
+        self.on_result.push(OnResult(Arc::new(function)));
+        self
+    }
 
-fn respond_to_request(
-    stream: &mut TcpStream,
-    dist_dir_path: impl AsRef<Path>,
-    not_found_path: Option<impl AsRef<Path>>,
-) -> Result<()> {
-    let mut reader = BufReader::new(stream);
-    let mut request = String::new();
-    reader.read_line(&mut request)?;
+    /// Show a desktop notification after each run of the command, like
+    /// "Build failed after 8s", to notice the failures while working in
+    /// another window.
+    ///
+    /// This uses `notify-send` on Linux and `osascript` on macOS, the option
+    /// is ignored on the other platforms.
+    pub fn desktop_notifications(mut self, res: bool) -> Self {
+        self.desktop_notifications = res;
+        self
+    }
 
-    let requested_path = request
-        .split_whitespace()
-        .nth(1)
-        .context("Could not find path in request")?;
+    /// Stop the server after the given number of runs of the command, to use
+    /// the watch in scripts, like a soak test.
+    ///
+    /// The runs interrupted by a change are not counted.
+    pub fn max_runs(mut self, max_runs: usize) -> Self {
+        self.max_runs = Some(max_runs);
+        self
+    }
 
-    let requested_path = requested_path
-        .split_once('?')
-        .map(|(prefix, _suffix)| prefix)
-        .unwrap_or(requested_path);
+    /// Stop the server once the command succeeded, to fix the build until it
+    /// succeeds before doing something else.
+    pub fn exit_on_success(mut self, res: bool) -> Self {
+        self.exit_on_success = res;
+        self
+    }
 
-    log::debug!("<-- {}", requested_path);
+    /// Execute the command again when it exits, to keep a command serving
+    /// the backend running when it crashes.
+    ///
+    /// Like after a change, the command is executed again once the debounce
+    /// elapsed, and later when it keeps failing: 1s after the second failure
+    /// in a row, doubling up to 30s.
+    pub fn restart_on_exit(mut self, res: bool) -> Self {
+        self.restart_on_exit = res;
+        self
+    }
 
-    let rel_path = Path::new(requested_path.trim_matches('/'));
-    let mut full_path = dist_dir_path.as_ref().join(rel_path);
+    /// Give the variables of an environment file to the command, reading the
+    /// file again before each run so its changes are taken into account.
+    ///
+    /// The file contains `KEY=value` lines, optionally prefixed by `export`,
+    /// with `#` comments and values in quotes. The variables removed from the
+    /// file are removed from the environment of the command. The file is only
+    /// watched with [`Self::watch_hidden`] if its name starts with a dot.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().env_file(".env").watch_hidden(true);
+    /// ```
+    pub fn env_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.env_file = Some(path.into());
+        self
+    }
 
-    if full_path.is_dir() {
-        if full_path.join("index.html").exists() {
-            full_path = full_path.join("index.html")
-        } else if full_path.join("index.htm").exists() {
-            full_path = full_path.join("index.htm")
-        } else {
-            bail!("no index.html in {}", full_path.display());
-        }
+    /// Receive the changes of the watched paths, to react to them in the
+    /// xtask, like rebuilding in the same process.
+    ///
+    /// The changes are debounced and filtered like the ones executing the
+    /// command, and received even without command. The changes made while the
+    /// previous ones are not received yet are still sent.
+    ///
+    /// ```rust,no_run
+    /// # use std::thread;
+    /// # use xtask_wasm::{anyhow::Result, DevServer};
+    /// # fn main() -> Result<()> {
+    /// let mut server = DevServer::default();
+    /// let changes = server.change_events();
+    /// thread::spawn(move || {
+    ///     for change in changes {
+    ///         println!("changed: {:?}", change.changed_paths);
+    ///     }
+    /// });
+    /// server.start("dist")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn change_events(&mut self) -> mpsc::Receiver<WatchContext> {
+        let (sender, receiver) = mpsc::channel();
+        self.change_senders.push(sender);
+        receiver
     }
 
-    let stream = reader.get_mut();
+    /// Handle to run the command again from the xtask, like after generating
+    /// code or assets with another tool.
+    ///
+    /// On Unix, sending `SIGUSR1` to the process also runs the command again.
+    ///
+    /// ```rust,no_run
+    /// # use std::{thread, time::Duration};
+    /// # use xtask_wasm::{anyhow::Result, DevServer};
+    /// # fn main() -> Result<()> {
+    /// let server = DevServer::default();
+    /// let trigger = server.trigger();
+    /// thread::spawn(move || loop {
+    ///     thread::sleep(Duration::from_secs(60));
+    ///     trigger.trigger();
+    /// });
+    /// server.start("dist")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn trigger(&self) -> WatchTrigger {
+        self.trigger.clone()
+    }
+
+    /// Use another file path when the URL is not found.
+    pub fn not_found(mut self, path: impl Into<PathBuf>) -> Self {
+        self.not_found_path.replace(path.into());
+        self
+    }
+
+    /// Use an HTML page, relative to the served directory, as the body of
+    /// the responses with the given status.
+    ///
+    /// Unlike [`not_found`](Self::not_found), the status of the response is
+    /// preserved. The page is only used when the client accepts `text/html`,
+    /// the requests accepting JSON get a
+    /// [problem details](https://www.rfc-editor.org/rfc/rfc7807) body
+    /// instead. The requests that make a handler fail are answered with
+    /// `500`.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default()
+    ///     .error_page(404, "404.html")
+    ///     .error_page(500, "500.html");
+    /// ```
+    pub fn error_page(mut self, status: u16, path: impl Into<PathBuf>) -> Self {
+        self.error_pages.push((status, path.into()));
+        self
+    }
+
+    /// Serve the index file of the served path for the navigations to unknown
+    /// paths, for single-page applications using the history API. The first
+    /// existing file of [`Self::index_files`] is used, `index.html` by default.
+    ///
+    /// Unlike [`not_found`](Self::not_found), only the extension-less paths
+    /// requested as `text/html` are rewritten, missing assets are still
+    /// reported as `404 Not Found`.
+    pub fn spa(mut self, res: bool) -> Self {
+        self.spa = res;
+        self
+    }
+
+    /// Serve `about.html` when `/about` is requested, like most static site
+    /// hosts.
+    ///
+    /// The `.html` file is only used when the requested path has no extension
+    /// and doesn't exist, before [`spa`](Self::spa) and
+    /// [`not_found`](Self::not_found) are applied.
+    pub fn clean_urls(mut self, res: bool) -> Self {
+        self.clean_urls = res;
+        self
+    }
+
+    /// Serve the files of a directory under a path prefix.
+    ///
+    /// This can be called multiple times, the longest matching prefix is used
+    /// and the served path is mounted at `/`.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::{anyhow::Result, default_dist_dir, DevServer};
+    /// # fn main() -> Result<()> {
+    /// DevServer::default()
+    ///     .mount("/docs", "target/doc")
+    ///     .mount("/assets", "shared/assets")
+    ///     .start(default_dist_dir(false))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn mount(mut self, prefix: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.mounts.push((prefix.into(), path.into()));
+        self
+    }
+
+    /// Serve another directory on another port of the same address, like the
+    /// generated `cargo doc` or a component gallery.
+    ///
+    /// The directory is served by the same server, sharing the watch process
+    /// and the other options, instead of running a second development
+    /// server. This can be called multiple times.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().secondary_port(8001, "target/doc");
+    /// ```
+    pub fn secondary_port(mut self, port: u16, path: impl Into<PathBuf>) -> Self {
+        self.secondary_ports.push((port, path.into()));
+        self
+    }
+
+    /// Forward the requests whose path starts with `prefix` to a backend.
+    ///
+    /// The prefix is replaced by the path of the backend URL and the headers
+    /// are preserved, except `Host`. Only `http://` backends are supported.
+    /// This can be called multiple times, the longest matching prefix is used.
+    ///
+    /// Connecting to the backend and each read and write of the exchange with
+    /// it time out after [`Self::header_timeout`], or [`Self::timeout`] without
+    /// it.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().proxy("/api", "http://127.0.0.1:3000");
+    /// ```
+    pub fn proxy(mut self, prefix: impl Into<String>, backend: impl Into<String>) -> Self {
+        self.proxies.push((prefix.into(), backend.into()));
+        self
+    }
+
+    /// Redirect the requests of a path to another path or URL, with the given
+    /// status like `301` or `302`.
+    ///
+    /// The query string of the request is kept unless `to` has one.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default()
+    ///     .redirect("/old", "/new", 301)
+    ///     .redirect("/docs", "https://docs.rs/xtask-wasm", 302);
+    /// ```
+    pub fn redirect(mut self, from: impl Into<String>, to: impl Into<String>, status: u16) -> Self {
+        self.redirects.push((from.into(), to.into(), status));
+        self
+    }
+
+    /// Serve the requests whose path starts with `from` as if they started
+    /// with `to`, without redirecting the client.
+    ///
+    /// The rewrites are applied before the mocks, the proxies and the files
+    /// are looked up. The longest matching prefix is used.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// // `/app/logo.png` is served from `logo.png`
+    /// DevServer::default().rewrite("/app", "/");
+    /// ```
+    pub fn rewrite(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.rewrites.push((from.into(), to.into()));
+        self
+    }
+
+    /// Answer the requests matching a fixture of the given directory.
+    ///
+    /// The request path is mapped to a `.json`, `.yaml` or `.yml` file, `index`
+    /// being used for the paths ending with a `/`. The method and the status
+    /// of the response can be annotated before the extension: `users.json`
+    /// answers every method with `200`, `users.POST.201.yaml` only answers
+    /// `POST` with `201`. YAML fixtures are converted to JSON. The requests
+    /// without fixture are served as usual.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().mock_api("mocks/");
+    /// ```
+    pub fn mock_api(mut self, path: impl Into<PathBuf>) -> Self {
+        self.mock_api = Some(path.into());
+        self
+    }
+
+    /// Add a middleware handling the requests before the default request
+    /// handler.
+    ///
+    /// The middlewares are called in the order they are added, each one can
+    /// respond to the request itself or pass it to the next one.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default()
+    ///     .middleware(|request, next| {
+    ///         log::info!("{}", request.path);
+    ///         next.run(request)
+    ///     })
+    ///     .middleware(|request, next| {
+    ///         if request.header("authorization").is_none() {
+    ///             request.stream.write_all(
+    ///                 b"HTTP/1.1 401 UNAUTHORIZED\r\nContent-Length: 0\r\n\r\n"
+    ///             )?;
+    ///             return Ok(());
+    ///         }
+    ///         next.run(request)
+    ///     });
+    /// ```
+    pub fn middleware(
+        mut self,
+        middleware: impl Fn(Request, Next) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.middlewares.push(Middleware(Arc::new(middleware)));
+        self
+    }
+
+    /// Add a request handler answering only some of the requests.
+    ///
+    /// The requests it returns with [`HandlerResult::NotHandled`] are passed
+    /// to the next middlewares, to the routes and to the default request
+    /// handler serving the files. It is called in the order of the
+    /// middlewares.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::{DevServer, HandlerResult};
+    /// DevServer::default().request_handler(|request| {
+    ///     if !request.path.starts_with("/api/") {
+    ///         return Ok(HandlerResult::NotHandled(request));
+    ///     }
+    ///     request
+    ///         .stream
+    ///         .write_all(b"HTTP/1.1 204 NO CONTENT\r\n\r\n")?;
+    ///     Ok(HandlerResult::Handled)
+    /// });
+    /// ```
+    pub fn request_handler(
+        self,
+        handler: impl Fn(Request) -> Result<HandlerResult> + Send + Sync + 'static,
+    ) -> Self {
+        self.middleware(move |request, next| match handler(request)? {
+            HandlerResult::Handled => Ok(()),
+            HandlerResult::NotHandled(request) => next.run(request),
+        })
+    }
+
+    /// Handle the requests of the given path, instead of the default request
+    /// handler.
+    ///
+    /// The routes are called after the middlewares. The query string is not
+    /// part of the matched path.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().route("/healthz", |request| {
+    ///     request
+    ///         .stream
+    ///         .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")?;
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn route(
+        mut self,
+        path: impl Into<String>,
+        handler: impl Fn(Request) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.push(Route {
+            path: path.into(),
+            prefix: false,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Handle the requests whose path starts with `prefix`, instead of the
+    /// default request handler.
+    ///
+    /// The routes registered with [`Self::route`] take precedence, then the
+    /// longest matching prefix is used.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().route_prefix("/api", |request| {
+    ///     let body = format!("{{\"path\":\"{}\"}}", request.path);
+    ///     write!(
+    ///         request.stream,
+    ///         "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+    ///         Content-Length: {}\r\n\r\n{}",
+    ///         body.len(),
+    ///         body,
+    ///     )?;
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn route_prefix(
+        mut self,
+        prefix: impl Into<String>,
+        handler: impl Fn(Request) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.push(Route {
+            path: prefix.into(),
+            prefix: true,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Add a header to every response.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default()
+    ///     .header("Cross-Origin-Opener-Policy", "same-origin")
+    ///     .header("Cross-Origin-Embedder-Policy", "require-corp");
+    /// ```
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the content type of the files with the given extension.
+    ///
+    /// The most common types of the web are already known, this overrides
+    /// them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// let dev_server = DevServer::default().mime("ktx2", "image/ktx2");
+    /// ```
+    pub fn mime(mut self, extension: impl Into<String>, content_type: impl Into<String>) -> Self {
+        self.mime_types
+            .push((extension.into(), content_type.into()));
+        self
+    }
+
+    /// Set the `Cache-Control` header of the served files.
+    ///
+    /// The files are served with an `ETag` and a `Last-Modified` header and
+    /// conditional requests are answered with `304 Not Modified`. The default,
+    /// `no-cache`, makes the browser revalidate the files on every load.
+    pub fn cache_control(mut self, value: impl Into<String>) -> Self {
+        self.cache_control = value.into();
+        self
+    }
+
+    /// Set the `Cache-Control` header of the files matching a pattern.
+    ///
+    /// A pattern without `/` is matched against the name of the served file,
+    /// otherwise against the requested path. `*` matches any sequence of
+    /// characters. The last matching rule is used, the files matching no rule
+    /// are served with [`Self::cache_control`].
+    ///
+    /// Useful to check the caching strategy of a production deployment, with
+    /// the hashed assets cached for a long time while the pages are
+    /// revalidated:
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default()
+    ///     .cache_control_for("/assets/*", "public, max-age=31536000, immutable")
+    ///     .cache_control_for("*.html", "no-cache");
+    /// ```
+    pub fn cache_control_for(
+        mut self,
+        pattern: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.cache_control_rules
+            .push((pattern.into(), value.into()));
+        self
+    }
+
+    /// Send the headers required for a cross-origin isolated context.
+    ///
+    /// This is needed to use `SharedArrayBuffer`, for example when the dist
+    /// is built with [`Dist::threads`](crate::Dist::threads).
+    pub fn cross_origin_isolation(mut self, res: bool) -> Self {
+        self.cross_origin_isolation = res;
+        self
+    }
+
+    /// Accept requests for the given host name.
+    ///
+    /// To protect against DNS rebinding attacks, the requests are rejected
+    /// unless their `Host` header is `localhost`, a subdomain of `localhost`,
+    /// an IP address or an allowed host. A leading dot, like `.example.com`,
+    /// allows every subdomain.
+    pub fn allowed_host(mut self, host: impl Into<String>) -> Self {
+        self.allowed_hosts.push(host.into());
+        self
+    }
+
+    /// Accept requests whatever their `Host` header, see
+    /// [`Self::allowed_host`].
+    pub fn disable_host_check(mut self, res: bool) -> Self {
+        self.disable_host_check = res;
+        self
+    }
+
+    /// Reload the browsers when the command finishes successfully.
+    ///
+    /// A small WebSocket client is injected in the served HTML files. The
+    /// address of the server is given to the command in the
+    /// `XTASK_WASM_DEV_SERVER` environment variable so
+    /// [`Dist::run`](crate::Dist::run) can notify it at the end of the build,
+    /// along with a random token in `XTASK_WASM_DEV_SERVER_TOKEN` that the
+    /// notifications must carry.
+    ///
+    /// When the stylesheets are the only files that changed, they are replaced
+    /// in place instead, preserving the state of the application.
+    ///
+    /// When the build fails, the errors of cargo are shown over the page until
+    /// the next successful build.
+    ///
+    /// Without a command, the served directory is watched instead: the
+    /// browsers are updated once its content stopped changing, for instance
+    /// after running `cargo xtask dist` in another terminal.
+    pub fn reload(mut self, res: bool) -> Self {
+        self.reload = res;
+        self
+    }
+
+    /// Run the command once, waiting for it to finish, before starting to
+    /// serve.
+    ///
+    /// This prevents the first page load from failing while the initial build
+    /// is still running. The server is started even if the build fails.
+    pub fn wait_for_build(mut self, res: bool) -> Self {
+        self.wait_for_build = res;
+        self
+    }
+
+    /// Prefix each line written by the command with `[build]` and the
+    /// messages logged by the server with `[serve]`, colored when the
+    /// terminal supports it, to tell them apart.
+    ///
+    /// Only supported on Unix, the option is ignored on the other platforms.
+    pub fn prefix_output(mut self, res: bool) -> Self {
+        self.prefix_output = res;
+        self
+    }
+
+    /// Set the file names served for the requests of a directory, in order of
+    /// preference. Default to `index.html` and `index.htm`.
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().index_files(["index.html", "default.html", "200.html"]);
+    /// ```
+    pub fn index_files(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.index_files = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Render a listing of the content of the requested directories that
+    /// don't have an index file, see [`Self::index_files`].
+    ///
+    /// Useful when serving documentation or asset trees, see
+    /// [`Self::mount`].
+    pub fn directory_listing(mut self, res: bool) -> Self {
+        self.directory_listing = res;
+        self
+    }
+
+    /// Serve the symbolic links pointing outside of the served directories.
+    ///
+    /// By default, the requests whose path resolves outside of the served
+    /// directory or of the mounted directories are answered with
+    /// `403 Forbidden`.
+    pub fn follow_symlinks(mut self, res: bool) -> Self {
+        self.follow_symlinks = res;
+        self
+    }
+
+    /// Keep the files of the served directory in memory.
+    ///
+    /// The content and the metadata of a file are read from the disk on its
+    /// first request only, which makes reloading large WebAssembly bundles
+    /// faster. The cache is emptied after each build, or when the served
+    /// directory changes if the server doesn't run a command. The mounted
+    /// directories are not cached.
+    pub fn cache_files(mut self, res: bool) -> Self {
+        self.cache_files = res;
+        self
+    }
+
+    /// Write the requests to the given file, in addition to the debug logs.
+    ///
+    /// The lines are appended to the file if it already exists.
+    pub fn access_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.access_log = Some(path.into());
+        self
+    }
+
+    /// Set the format of the logged requests.
+    ///
+    /// The placeholders `{method}`, `{path}` (without the query string),
+    /// `{target}` (with the query string), `{status}`, `{bytes}` (size of the
+    /// response), `{duration}`, `{time}` and `{user_agent}` are replaced by
+    /// their value. Default to `{method} {path} {status} {bytes} {duration}`.
+    pub fn access_log_format(mut self, format: impl Into<String>) -> Self {
+        self.access_log_format = format.into();
+        self
+    }
+
+    /// Slow down the responses to simulate a slow network.
+    ///
+    /// Useful to check the loading states of the application or the streaming
+    /// compilation of the Wasm module. The live reload is not throttled.
+    ///
+    /// ```rust,no_run
+    /// # use std::time::Duration;
+    /// # use xtask_wasm::{DevServer, ThrottleConfig};
+    /// DevServer::default().throttle(ThrottleConfig {
+    ///     latency: Duration::from_millis(300),
+    ///     bytes_per_sec: Some(200_000),
+    /// });
+    /// ```
+    pub fn throttle(mut self, config: ThrottleConfig) -> Self {
+        self.throttle = Some(config);
+        self
+    }
+
+    /// Serve over HTTPS using a throwaway self-signed certificate.
+    ///
+    /// This provides a secure context, required by some web APIs like the
+    /// clipboard or WebAuthn, when the server is not accessed through
+    /// `localhost`. The browser will warn about the certificate.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+    pub fn tls_self_signed(mut self) -> Self {
+        self.tls = true;
+        self
+    }
+
+    /// Serve over HTTPS using the given PEM encoded certificate chain and
+    /// private key.
+    ///
+    /// Useful with certificates issued by an internal CA or generated with
+    /// [mkcert](https://github.com/FiloSottile/mkcert), that are trusted by
+    /// the browser.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+    pub fn tls(mut self, certificate: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.tls_certificate = Some((certificate.into(), key.into()));
+        self
+    }
+
+    /// Offer HTTP/2 to the browsers connecting over HTTPS.
+    ///
+    /// The requests of a page are multiplexed on a single connection instead
+    /// of waiting for each other. Browsers only use HTTP/2 over TLS, it has no
+    /// effect without [`Self::tls_self_signed`] or [`Self::tls`].
+    #[cfg(all(feature = "async-server", feature = "tls"))]
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "async-server", feature = "tls"))))]
+    pub fn http2(mut self, res: bool) -> Self {
+        self.http2 = res;
+        self
+    }
+
+    /// Start the server, serving the files at `served_path`.
+    ///
+    /// [`crate::default_dist_dir`] should be used to get the dist directory
+    /// that needs to be served.
+    pub fn start(mut self, served_path: impl AsRef<Path>) -> Result<()> {
+        let watch_dist_dir = self.watch_dist_dir();
+        let (listeners, served_path, watch_process) = self.prepare(served_path.as_ref())?;
+
+        let res = serve(self, listeners, served_path, watch_dist_dir)
+            .context("an error occurred when starting to serve");
+
+        if let Some(handle) = watch_process {
+            handle.stop();
+            handle.join();
+        }
+
+        res
+    }
+
+    /// Start the server in the background, serving the files at
+    /// `served_path`.
+    ///
+    /// Unlike [`Self::start`], this returns as soon as the server is
+    /// listening, with a handle to stop it. Useful in integration tests.
+    ///
+    /// ```rust,no_run
+    /// # use std::net::Ipv4Addr;
+    /// # use xtask_wasm::{anyhow::Result, DevServer};
+    /// # fn main() -> Result<()> {
+    /// let server = DevServer::default()
+    ///     .address(Ipv4Addr::LOCALHOST.into(), 0)
+    ///     .spawn("dist")?;
+    /// println!("listening on {}", server.addr());
+    /// // ...
+    /// server.shutdown()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn spawn(mut self, served_path: impl AsRef<Path>) -> Result<DevServerHandle> {
+        let watch_dist_dir = self.watch_dist_dir();
+        let (listeners, served_path, watch_process) = self.prepare(served_path.as_ref())?;
+
+        let wake_addresses = listeners
+            .iter()
+            .map(|(listener, _)| listener.wake_address())
+            .collect::<io::Result<_>>()?;
+        let handle = DevServerHandle {
+            addr: notify_address(&self),
+            urls: self.urls(),
+            shutdown: self.shutdown.clone(),
+            wake_addresses,
+            watch: watch_process,
+            thread: thread::spawn(move || {
+                serve(self, listeners, served_path, watch_dist_dir)
+                    .context("an error occurred when starting to serve")
+            }),
+        };
+
+        Ok(handle)
+    }
+
+    /// Whether the served directory needs to be watched, the builds of the
+    /// command being only notified to the server when reloading.
+    ///
+    /// This needs to be called before [`Self::prepare`] takes the commands.
+    fn watch_dist_dir(&self) -> bool {
+        let has_command = self.command.is_some()
+            || self.run_with.is_some()
+            || !self.pattern_commands.is_empty()
+            || !self.parallel_commands.is_empty();
+        (self.reload || self.cache_files) && !(self.reload && has_command)
+    }
+
+    /// Bind the listeners and start watching, before serving.
+    #[allow(clippy::type_complexity)]
+    fn prepare(
+        &mut self,
+        served_path: &Path,
+    ) -> Result<(
+        Vec<(Listener, Arc<PathBuf>)>,
+        Arc<PathBuf>,
+        Option<WatchHandle>,
+    )> {
+        let mut commands: Vec<_> = self
+            .command
+            .take()
+            .into_iter()
+            .chain(self.then_commands.drain(..))
+            .collect();
+        let routes = self.pattern_commands.iter_mut().map(|(_, command)| command);
+        if self.prefix_output {
+            for command in commands
+                .iter_mut()
+                .chain(routes)
+                .chain(&mut self.parallel_commands)
+            {
+                prefix_output(command)?;
+            }
+        }
+
+        // NOTE: the watch sets the variables again before each run
+        if self.wait_for_build {
+            if let Some(vars) = self.env_file.as_deref().and_then(watcher::read_env_file) {
+                for command in &mut commands {
+                    command.envs(vars.iter().map(|(key, value)| (key, value)));
+                }
+            }
+        }
+
+        let mut steps: Vec<_> = self
+            .run_with
+            .take()
+            .map(Step::Function)
+            .into_iter()
+            .chain(commands.into_iter().map(Step::Command))
+            .collect();
+        if self.wait_for_build && !steps.is_empty() {
+            log::info!("Waiting for the first build");
+            for step in &mut steps {
+                if !step.execute(&WatchContext::default())? {
+                    log::error!("the first build failed");
+                    break;
+                }
+            }
+        }
+
+        let served_path = Arc::new(served_path.to_path_buf());
+        let mut listeners: Vec<_> = match &self.unix_socket {
+            Some(path) => vec![bind_unix(path)?],
+            None => self.bind_tcp()?,
+        }
+        .into_iter()
+        .map(|listener| (listener, served_path.clone()))
+        .collect();
+        for (port, path) in &self.secondary_ports {
+            let listener = TcpListener::bind((self.ip, *port))
+                .with_context(|| format!("could not listen on port {}", port))?;
+            listeners.push((Listener::Tcp(listener), Arc::new(path.clone())));
+        }
+
+        let mut routes: Vec<_> = self
+            .pattern_commands
+            .drain(..)
+            .map(|(pattern, command)| (pattern, Step::Command(command)))
+            .collect();
+        let mut parallel: Vec<_> = self
+            .parallel_commands
+            .drain(..)
+            .map(Step::Command)
+            .collect();
+        let watch_process = if !steps.is_empty()
+            || !routes.is_empty()
+            || !parallel.is_empty()
+            || !self.change_senders.is_empty()
+        {
+            if self.reload {
+                let address = match &self.unix_socket {
+                    Some(path) => format!("unix:{}", path.display()),
+                    None => notify_address(self).to_string(),
+                };
+                self.notify_token = live_reload::new_token();
+                for step in steps
+                    .iter_mut()
+                    .chain(routes.iter_mut().map(|(_, x)| x))
+                    .chain(&mut parallel)
+                {
+                    match step {
+                        Step::Command(command) => {
+                            command
+                                .env(live_reload::ADDRESS_ENV, &address)
+                                .env(live_reload::TOKEN_ENV, &self.notify_token);
+                        }
+                        // NOTE: the function notifies the server from this process
+                        Step::Function(_) => {
+                            std::env::set_var(live_reload::ADDRESS_ENV, &address);
+                            std::env::set_var(live_reload::TOKEN_ENV, &self.notify_token);
+                        }
+                    }
+                }
+            }
+
+            let served_paths = std::iter::once(served_path.to_path_buf())
+                .chain(self.secondary_ports.iter().map(|(_, path)| path.clone()));
+            let mut watch = workspace_watch_config(std::mem::take(&mut self.watch))?;
+            for path in served_paths.chain(member_target_dirs()) {
+                // NOTE: the path needs to exists in order to be excluded because it is canonicalize
+                let _ = std::fs::create_dir_all(&path);
+                watch = watch.exclude_path(path);
+            }
+            let metadata = crate::metadata();
+            let mut watcher = Watcher::new(
+                watch,
+                metadata.workspace_root.as_std_path(),
+                metadata.target_directory.as_std_path(),
+            )?;
+            watcher.watch_globs = self.watch_globs.clone();
+            watcher.exclude_globs = self.exclude_globs.clone();
+            watcher.use_gitignore = self.use_gitignore;
+            watcher.watch_hidden = self.watch_hidden;
+            watcher.extensions = self.watch_extensions.clone();
+            watcher.filters = std::mem::take(&mut self.watch_filters);
+            watcher.event_kinds = self.watch_event_kinds.clone();
+            watcher.clear_terminal = self.clear_terminal;
+            watcher.interactive = self.interactive;
+            watcher.on_result = std::mem::take(&mut self.on_result);
+            watcher.desktop_notifications = self.desktop_notifications;
+            watcher.max_runs = self.max_runs;
+            watcher.exit_on_success = self.exit_on_success;
+            watcher.restart_on_exit = self.restart_on_exit;
+            watcher.env_file = self.env_file.clone();
+            watcher.change_senders = std::mem::take(&mut self.change_senders);
+            watcher.trigger = self.trigger.clone();
+            watcher.steps = steps;
+            watcher.routes = routes;
+            watcher.parallel = parallel;
+            let shutdown = self.shutdown.clone();
+            let wake_addresses = listeners
+                .iter()
+                .map(|(listener, _)| listener.wake_address())
+                .collect::<io::Result<Vec<_>>>()?;
+            let quit = move || stop(&shutdown, &wake_addresses);
+
+            Some(watcher.spawn(quit)?)
+        } else {
+            None
+        };
+
+        Ok((listeners, served_path, watch_process))
+    }
+
+    /// Bind the TCP listeners, updating the port if it changed.
+    fn bind_tcp(&mut self) -> Result<Vec<Listener>> {
+        let (ip, other_ip) = if self.dual_stack {
+            dual_stack_ips(self.ip)
+        } else {
+            (self.ip, None)
+        };
+
+        let listener = bind(self, ip)?;
+        let port = listener.local_addr()?.port();
+        if port != self.port {
+            log::info!("Port {} is not available, using {}", self.port, port);
+            self.port = port;
+        }
+
+        let mut listeners = vec![Listener::Tcp(listener)];
+        if let Some(ip) = other_ip {
+            match TcpListener::bind((ip, port)) {
+                Ok(listener) => listeners.push(Listener::Tcp(listener)),
+                // NOTE: on most systems an IPv6 socket also accepts IPv4
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse && ip.is_unspecified() => {
+                    log::debug!("IPv4 connections are accepted by the IPv6 socket");
+                }
+                Err(err) => log::warn!("could not listen on {}: {}", ip, err),
+            }
+        }
+
+        Ok(listeners)
+    }
+
+    /// URLs where the server can be reached.
+    ///
+    /// When the server is bound to an unspecified address like `0.0.0.0`, the
+    /// URL using `localhost` is followed by the URLs of every network interface
+    /// of the machine, useful to test on other devices. The port is the one
+    /// actually used once the server is started.
+    pub fn urls(&self) -> Vec<String> {
+        let mut addresses = vec![notify_address(self)];
+        if self.ip.is_unspecified() {
+            addresses.extend(
+                interface_addresses()
+                    .into_iter()
+                    .filter(|ip| {
+                        !ip.is_loopback() && (self.ip.is_ipv6() || self.dual_stack || ip.is_ipv4())
+                    })
+                    // NOTE: link-local IPv6 addresses require a scope
+                    .filter(
+                        |ip| !matches!(ip, IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80),
+                    )
+                    .map(|ip| SocketAddr::new(ip, self.port)),
+            );
+        }
+
+        addresses
+            .into_iter()
+            .map(|address| format!("{}://{}", self.scheme(), address))
+            .collect()
+    }
+
+    fn scheme(&self) -> &'static str {
+        #[cfg(feature = "tls")]
+        if self.tls || self.tls_certificate.is_some() {
+            return "https";
+        }
+
+        "http"
+    }
+
+    fn set_xtask_command(&mut self) -> &mut process::Command {
+        if self.command.is_none() {
+            self.command = Some(crate::xtask_command());
+        }
+        self.command.as_mut().unwrap()
+    }
+}
+
+impl Default for DevServer {
+    fn default() -> DevServer {
+        DevServer {
+            ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            port: 8000,
+            auto_port: false,
+            dual_stack: false,
+            unix_socket: None,
+            open: false,
+            open_path: "/".to_string(),
+            workers: 32,
+            max_connections: None,
+            timeout: None,
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            header_timeout: Some(Duration::from_secs(10)),
+            max_header_size: 16384,
+            max_body_size: 10 * 1024 * 1024,
+            #[cfg(feature = "mdns")]
+            mdns: None,
+            watch: Default::default(),
+            command: None,
+            then_commands: Vec::new(),
+            run_with: None,
+            pattern_commands: Vec::new(),
+            parallel_commands: Vec::new(),
+            watch_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            use_gitignore: true,
+            watch_hidden: false,
+            watch_extensions: Vec::new(),
+            watch_filters: Vec::new(),
+            watch_event_kinds: watcher::default_event_kinds(),
+            clear_terminal: false,
+            interactive: false,
+            on_result: Vec::new(),
+            desktop_notifications: false,
+            max_runs: None,
+            exit_on_success: false,
+            restart_on_exit: false,
+            env_file: None,
+            change_senders: Vec::new(),
+            trigger: WatchTrigger::default(),
+            not_found_path: None,
+            error_pages: Vec::new(),
+            spa: false,
+            clean_urls: false,
+            mounts: Vec::new(),
+            secondary_ports: Vec::new(),
+            proxies: Vec::new(),
+            redirects: Vec::new(),
+            rewrites: Vec::new(),
+            mock_api: None,
+            middlewares: Vec::new(),
+            routes: Vec::new(),
+            headers: Vec::new(),
+            mime_types: Vec::new(),
+            cache_control: "no-cache".to_string(),
+            cache_control_rules: Vec::new(),
+            cross_origin_isolation: false,
+            allowed_hosts: Vec::new(),
+            disable_host_check: false,
+            reload: false,
+            wait_for_build: false,
+            prefix_output: false,
+            index_files: vec!["index.html".to_string(), "index.htm".to_string()],
+            directory_listing: false,
+            follow_symlinks: false,
+            cache_files: false,
+            access_log: None,
+            access_log_format: access_log::DEFAULT_FORMAT.to_string(),
+            access_log_file: None,
+            throttle: None,
+            #[cfg(feature = "tls")]
+            tls: false,
+            #[cfg(feature = "tls")]
+            tls_certificate: None,
+            #[cfg(all(feature = "async-server", feature = "tls"))]
+            http2: false,
+            shutdown: Default::default(),
+            file_cache: None,
+            notify_token: String::new(),
+            metrics: Default::default(),
+        }
+    }
+}
+
+/// A development server running in the background, started by
+/// [`DevServer::spawn`].
+#[derive(Debug)]
+pub struct DevServerHandle {
+    addr: SocketAddr,
+    urls: Vec<String>,
+    shutdown: Arc<AtomicBool>,
+    wake_addresses: Vec<WakeAddress>,
+    watch: Option<WatchHandle>,
+    thread: thread::JoinHandle<Result<()>>,
+}
+
+impl DevServerHandle {
+    /// Address to reach the server, with the port actually used.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URLs to reach the server, see [`DevServer::urls`].
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    /// Wait for the server to stop, returning its error if it failed.
+    ///
+    /// The watch of the command, if any, is stopped once the server stopped.
+    pub fn wait(self) -> Result<()> {
+        let res = self
+            .thread
+            .join()
+            .unwrap_or_else(|_| Err(crate::anyhow::anyhow!("the server panicked")));
+        if let Some(watch) = self.watch {
+            watch.stop();
+            watch.join();
+        }
+        res
+    }
+
+    /// Stop accepting connections and wait for the server to stop.
+    ///
+    /// The connections being served are not interrupted. The watch of the
+    /// command, if any, is stopped and the command being executed is killed.
+    /// The summary of the handled requests is logged once the server stopped.
+    pub fn shutdown(self) -> Result<()> {
+        stop(&self.shutdown, &self.wake_addresses);
+        self.wait()
+    }
+}
+
+/// Stop accepting connections.
+fn stop(shutdown: &AtomicBool, wake_addresses: &[WakeAddress]) {
+    shutdown.store(true, Ordering::SeqCst);
+    // NOTE: a connection wakes up the threads waiting to accept one
+    for address in wake_addresses {
+        let _ = match address {
+            WakeAddress::Tcp(address) => TcpStream::connect(address).map(drop),
+            #[cfg(unix)]
+            WakeAddress::Unix(path) => std::os::unix::net::UnixStream::connect(path).map(drop),
+        };
+    }
+}
+
+/// Address used to connect to a listener to stop it.
+#[derive(Debug)]
+enum WakeAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Network conditions simulated by [`DevServer::throttle`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThrottleConfig {
+    /// Delay before handling each request.
+    pub latency: Duration,
+    /// Maximum speed at which the responses are sent, unlimited if `None`.
+    pub bytes_per_sec: Option<u64>,
+}
+
+/// Writer limiting the speed at which a response is sent.
+struct Throttled<'a> {
+    inner: &'a mut dyn Write,
+    bytes_per_sec: u64,
+    start: Instant,
+    written: u64,
+}
+
+impl Write for Throttled<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // NOTE: small writes make the transfer smoother
+        let max_len = (self.bytes_per_sec / 10).max(1) as usize;
+        let written = self.inner.write(&buf[..buf.len().min(max_len)])?;
+        self.written += written as u64;
+
+        let expected = Duration::from_secs_f64(self.written as f64 / self.bytes_per_sec as f64);
+        if let Some(delay) = expected.checked_sub(self.start.elapsed()) {
+            self.inner.flush()?;
+            thread::sleep(delay);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Addresses to listen on to accept both IPv4 and IPv6 connections.
+fn dual_stack_ips(ip: IpAddr) -> (IpAddr, Option<IpAddr>) {
+    match ip {
+        // NOTE: the IPv6 socket is bound first as it might accept IPv4 too
+        ip if ip.is_unspecified() => (
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        ),
+        IpAddr::V4(ip) if ip.is_loopback() => {
+            (IpAddr::V4(ip), Some(IpAddr::V6(Ipv6Addr::LOCALHOST)))
+        }
+        IpAddr::V6(ip) if ip.is_loopback() => {
+            (IpAddr::V6(ip), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)))
+        }
+        ip => {
+            log::warn!(
+                "{} is bound to a single stack, see `DevServer::dual_stack`",
+                ip
+            );
+            (ip, None)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<Listener> {
+    if path.exists() {
+        fs::remove_file(path).with_context(|| format!("could not remove `{}`", path.display()))?;
+    }
+
+    std::os::unix::net::UnixListener::bind(path)
+        .map(Listener::Unix)
+        .with_context(|| format!("cannot bind to `{}`", path.display()))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &Path) -> Result<Listener> {
+    bail!("Unix domain sockets are not supported on this platform")
+}
+
+/// Bind the listener, looking for a free port if `auto_port` is set.
+fn bind(server: &DevServer, ip: IpAddr) -> Result<TcpListener> {
+    let mut port = server.port;
+
+    loop {
+        match TcpListener::bind((ip, port)) {
+            Ok(listener) => return Ok(listener),
+            Err(err)
+                if server.auto_port
+                    && err.kind() == io::ErrorKind::AddrInUse
+                    && port != 0
+                    && port < u16::MAX =>
+            {
+                port += 1;
+            }
+            Err(err) => return Err(err).context("cannot bind to the given address"),
+        }
+    }
+}
+
+/// TLS configuration of the server, if any.
+#[cfg(feature = "tls")]
+pub(crate) type TlsConfig = Option<Arc<rustls::ServerConfig>>;
+#[cfg(not(feature = "tls"))]
+pub(crate) type TlsConfig = Option<NoTls>;
+
+/// TLS configuration of a server built without the `tls` feature.
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+pub(crate) enum NoTls {}
+
+/// A socket accepting the connections of the server.
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Listener {
+    fn wake_address(&self) -> io::Result<WakeAddress> {
+        match self {
+            Listener::Tcp(listener) => {
+                let mut address = listener.local_addr()?;
+                if address.ip().is_unspecified() {
+                    address.set_ip(match address.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                Ok(WakeAddress::Tcp(address))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .local_addr()?
+                .as_pathname()
+                .map(|path| WakeAddress::Unix(path.to_path_buf()))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unnamed Unix socket")),
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+fn serve(
+    mut server: DevServer,
+    listeners: Vec<(Listener, Arc<PathBuf>)>,
+    served_path: Arc<PathBuf>,
+    watch_dist_dir: bool,
+) -> Result<()> {
+    if let Some(path) = &server.access_log {
+        server.access_log_file = Some(Arc::new(access_log::AccessLogFile::open(path)?));
+    }
+    if server.cache_files {
+        server.file_cache = Some(Arc::new(FileCache::new(&served_path)));
+    }
+
+    #[cfg(feature = "tls")]
+    let tls_config: TlsConfig = if let Some((certificate, key)) = &server.tls_certificate {
+        Some(crate::tls::config_from_files(certificate, key)?)
+    } else if server.tls {
+        Some(crate::tls::self_signed_config(server.ip)?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "tls"))]
+    let tls_config: TlsConfig = None;
+    let scheme = server.scheme();
+
+    for (listener, path) in &listeners {
+        match listener {
+            Listener::Tcp(listener) if *path != served_path => log::info!(
+                "Serving {} at: {}://{}",
+                path.display(),
+                scheme,
+                listener.local_addr()?
+            ),
+            Listener::Tcp(listener) => log::info!(
+                "Development server running at: {}://{}",
+                scheme,
+                listener.local_addr()?
+            ),
+            #[cfg(unix)]
+            Listener::Unix(_) => {
+                if tls_config.is_some() {
+                    log::warn!("HTTPS is not supported on Unix domain sockets");
+                }
+                log::info!(
+                    "Development server running at: unix:{}",
+                    server
+                        .unix_socket
+                        .as_deref()
+                        .unwrap_or(Path::new(""))
+                        .display()
+                );
+            }
+        }
+    }
+    if server.ip.is_unspecified() {
+        let urls = server.urls();
+        for url in urls.iter().skip(1) {
+            log::info!("  On your network: {}", url);
+        }
+        #[cfg(feature = "qr")]
+        if let Some(url) = urls.get(1) {
+            print_qr_code(url);
+        }
+    }
+
+    if server.open && server.unix_socket.is_none() {
+        let url = format!(
+            "{}://{}/{}",
+            scheme,
+            notify_address(&server),
+            server.open_path.trim_start_matches('/')
+        );
+        if let Err(err) = open_browser(&url) {
+            log::warn!("could not open the browser: {}", err);
+        }
+    }
+
+    // NOTE: the service is announced as long as the daemon lives
+    #[cfg(feature = "mdns")]
+    let _mdns_daemon = match &server.mdns {
+        Some(name) => Some(announce_mdns(&server, name)?),
+        None => None,
+    };
+
+    let live_reload = Arc::new(live_reload::LiveReload::new(
+        &served_path,
+        server.file_cache.clone(),
+        server.notify_token.clone(),
+    ));
+    if watch_dist_dir {
+        live_reload.clone().watch_dist_dir(server.reload);
+    }
+
+    #[cfg(feature = "async-server")]
+    let serve_connections = crate::async_server::serve;
+    #[cfg(not(feature = "async-server"))]
+    let serve_connections = serve_threads;
+
+    let metrics = server.metrics.clone();
+    let result = serve_connections(listeners, Arc::new(server), live_reload, tls_config);
+    metrics.log_summary();
+
+    result
+}
+
+/// Serve the connections using a fixed number of threads.
+#[cfg_attr(feature = "async-server", allow(dead_code))]
+fn serve_threads(
+    listeners: Vec<(Listener, Arc<PathBuf>)>,
+    server: Arc<DevServer>,
+    live_reload: Arc<live_reload::LiveReload>,
+    tls_config: TlsConfig,
+) -> Result<()> {
+    ensure!(server.workers > 0, "at least one worker is required");
+
+    let (sender, receiver) = mpsc::channel::<(Stream, ConnectionGuard, Arc<PathBuf>)>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    for _ in 0..server.workers {
+        let receiver = receiver.clone();
+        let tls_config = tls_config.clone();
+        let server = server.clone();
+        let live_reload = live_reload.clone();
+
+        thread::spawn(move || loop {
+            let next = receiver
+                .lock()
+                .expect("receiver lock is not poisoned")
+                .recv();
+            let (stream, _guard, served_path) = match next {
+                Ok(connection) => connection,
+                Err(_) => break,
+            };
+
+            // NOTE: plain text connections on the HTTPS port only notify the builds
+            let (stream, notifications_only) = match (&tls_config, stream) {
+                #[cfg(feature = "tls")]
+                (Some(config), Stream::Tcp(stream)) => {
+                    let timeout = server.header_timeout.or(server.timeout);
+                    match crate::tls::accept(stream, config, timeout) {
+                        Ok(stream) => {
+                            let plain_text = matches!(stream, Stream::Tcp(_));
+                            (stream, plain_text)
+                        }
+                        Err(err) if is_timeout(&err) => {
+                            log::debug!("connection timed out");
+                            continue;
+                        }
+                        Err(err) => {
+                            log::error!("an error occurred: {:#}", err);
+                            continue;
+                        }
+                    }
+                }
+                #[cfg(not(feature = "tls"))]
+                (Some(never), _) => match *never {},
+                (_, stream) => (stream, false),
+            };
+
+            handle_connection(
+                stream,
+                &server,
+                &live_reload,
+                &served_path,
+                notifications_only,
+            );
+        });
+    }
+
+    let accepting: Vec<_> = listeners
+        .into_iter()
+        .map(|(listener, served_path)| {
+            let sender = sender.clone();
+            let server = server.clone();
+            let connections = connections.clone();
+            thread::spawn(move || loop {
+                let stream = listener.accept();
+                if is_shutting_down(&server) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+
+                let guard = ConnectionGuard::new(&connections);
+                if matches!(server.max_connections, Some(max) if guard.count > max) {
+                    log::warn!("too many connections, closing the new one");
+                    continue;
+                }
+                if let Err(err) = stream.set_timeout(server.timeout) {
+                    log::error!("could not set the connection timeout: {}", err);
+                }
+
+                sender
+                    .send((stream, guard, served_path.clone()))
+                    .expect("workers are running while the server is");
+            })
+        })
+        .collect();
+    for handle in accepting {
+        handle
+            .join()
+            .expect("an error occurred when accepting connections");
+    }
+
+    Ok(())
+}
+
+/// Whether the server has been asked to stop by [`DevServerHandle::shutdown`].
+pub(crate) fn is_shutting_down(server: &DevServer) -> bool {
+    server.shutdown.load(Ordering::SeqCst)
+}
+
+/// Count of the open connections, decremented when dropped.
+pub(crate) struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+    pub(crate) count: usize,
+}
+
+impl ConnectionGuard {
+    pub(crate) fn new(connections: &Arc<AtomicUsize>) -> ConnectionGuard {
+        ConnectionGuard {
+            connections: connections.clone(),
+            count: connections.fetch_add(1, Ordering::SeqCst) + 1,
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Respond to the successive requests of a connection until it is closed.
+///
+/// Only the build notifications are answered if `notifications_only` is set.
+fn handle_connection(
+    stream: Stream,
+    server: &DevServer,
+    live_reload: &live_reload::LiveReload,
+    dist_dir_path: &Path,
+    notifications_only: bool,
+) {
+    let mut reader = BufReader::new(stream);
+    let mut first_request = true;
+
+    loop {
+        let head_reader = HeadReader {
+            reader: &mut reader,
+            server,
+            started: if first_request {
+                Some(Instant::now())
+            } else {
+                None
+            },
+            size: 0,
+        };
+        first_request = false;
+        let result = ParsedRequest::read(head_reader);
+        if let Err(err) = reader.get_ref().set_timeout(server.timeout) {
+            log::error!("could not set the connection timeout: {}", err);
+            break;
+        }
+
+        let mut request = match result {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(err) if is_timeout(&err) => {
+                log::debug!("connection timed out");
+                break;
+            }
+            Err(err) if is_header_too_large(&err) => {
+                log::error!("the head of a request is too large");
+                let _ = reader.get_mut().write_all(
+                    b"HTTP/1.1 431 REQUEST HEADER FIELDS TOO LARGE\r\nContent-Length: 0\r\n\r\n",
+                );
+                let _ = reader.get_mut().flush();
+                break;
+            }
+            Err(err) => {
+                let _ = reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 400 BAD REQUEST\r\nContent-Length: 0\r\n\r\n");
+                let _ = reader.get_mut().flush();
+                log::error!("an error occurred: {}", err);
+                break;
+            }
+        };
+
+        // NOTE: the connection is closed as the rest of the body can't be skipped
+        let body_len = match (
+            request.header("transfer-encoding"),
+            request.header("content-length"),
+        ) {
+            (Some(_), _) => {
+                log::error!("--> {} (411 LENGTH REQUIRED)", request.path);
+                let _ = reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 411 LENGTH REQUIRED\r\nContent-Length: 0\r\n\r\n");
+                let _ = reader.get_mut().flush();
+                break;
+            }
+            (None, Some(len)) => match len.trim().parse::<u64>() {
+                Ok(len) if len > server.max_body_size => {
+                    log::error!("--> {} (413 PAYLOAD TOO LARGE)", request.path);
+                    let _ = reader
+                        .get_mut()
+                        .write_all(b"HTTP/1.1 413 PAYLOAD TOO LARGE\r\nContent-Length: 0\r\n\r\n");
+                    let _ = reader.get_mut().flush();
+                    break;
+                }
+                Ok(len) => len,
+                Err(_) => {
+                    log::error!("invalid content length `{}`", len);
+                    let _ = reader
+                        .get_mut()
+                        .write_all(b"HTTP/1.1 400 BAD REQUEST\r\nContent-Length: 0\r\n\r\n");
+                    let _ = reader.get_mut().flush();
+                    break;
+                }
+            },
+            (None, None) => 0,
+        };
+        if (&mut reader)
+            .take(body_len)
+            .read_to_end(&mut request.body)
+            .is_err()
+        {
+            break;
+        }
+
+        if notifications_only && !is_notification(server, &request) {
+            log::error!("--> {} (400 BAD REQUEST)", request.path);
+            let _ = reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 400 BAD REQUEST\r\nContent-Length: 0\r\n\r\n");
+            let _ = reader.get_mut().flush();
+            break;
+        }
+
+        if !host_allowed(server, &request) {
+            let _ = reject_host(reader.get_mut(), &request);
+            if reader.get_mut().flush().is_err() || !request.keep_alive() {
+                break;
+            }
+            continue;
+        }
+
+        if request.path == live_reload::STATUS_PATH {
+            if let Err(err) = live_reload.write_status(reader.get_mut(), &server.metrics) {
+                log::error!("an error occurred: {}", err);
+            }
+            if reader.get_mut().flush().is_err() || !request.keep_alive() {
+                break;
+            }
+            continue;
+        }
+
+        if server.reload && request.path == live_reload::RELOAD_PATH {
+            if let Err(err) = live_reload::handle(reader.get_mut(), &request, live_reload) {
+                log::error!("an error occurred: {}", err);
+            }
+            if request.method == "POST" {
+                continue;
+            }
+            // NOTE: the connection is now a WebSocket owned by the live reload
+            break;
+        }
+
+        if server.reload && request.path == live_reload::EVENTS_PATH {
+            if let Err(err) = live_reload::handle_events(reader.get_mut(), live_reload) {
+                log::error!("an error occurred: {}", err);
+            }
+            // NOTE: the connection is now an event stream owned by the live reload
+            break;
+        }
+
+        let keep_alive = request.keep_alive();
+        let result = handle_request(reader.get_mut(), &request, server, dist_dir_path);
+
+        let stream = reader.get_mut();
+        if let Err(err) = result {
+            let _ = stream.write_all(b"HTTP/1.1 400 BAD REQUEST\r\nContent-Length: 0\r\n\r\n");
+            log::error!("an error occurred: {}", err);
+        }
+
+        if stream.flush().is_err() || !keep_alive {
+            break;
+        }
+    }
+}
+
+/// Whether a request is a build notification sent to the live reload.
+fn is_notification(server: &DevServer, request: &ParsedRequest) -> bool {
+    server.reload && request.method == "POST" && request.path == live_reload::RELOAD_PATH
+}
+
+/// Reader of the head of a request, enforcing the keep-alive timeout, the
+/// header timeout and the maximum header size.
+struct HeadReader<'a> {
+    reader: &'a mut BufReader<Stream>,
+    server: &'a DevServer,
+    /// When the first byte of the request has been received.
+    started: Option<Instant>,
+    size: usize,
+}
+
+/// Error of a request whose head is larger than the maximum header size.
+#[derive(Debug)]
+struct HeaderTooLarge;
+
+impl fmt::Display for HeaderTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("request header too large")
+    }
+}
+
+impl std::error::Error for HeaderTooLarge {}
+
+impl Read for HeadReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for HeadReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.size > self.server.max_header_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, HeaderTooLarge));
+        }
+
+        if self.reader.buffer().is_empty() {
+            let timeout = match self.started {
+                Some(started) => match self.server.header_timeout {
+                    Some(timeout) => Some(
+                        timeout
+                            .checked_sub(started.elapsed())
+                            .filter(|x| !x.is_zero())
+                            .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))?,
+                    ),
+                    None => self.server.timeout,
+                },
+                None => self.server.keep_alive_timeout,
+            };
+            self.reader.get_ref().set_timeout(timeout)?;
+        }
+
+        let available = self.reader.fill_buf()?;
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+        Ok(available)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.size += amt;
+        self.reader.consume(amt);
+    }
+}
+
+/// Whether an error comes from a request whose head is too large.
+fn is_header_too_large(err: &crate::anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<io::Error>().and_then(|x| x.get_ref()),
+        Some(err) if err.is::<HeaderTooLarge>()
+    )
+}
+
+/// Whether an error comes from the timeout of a socket.
+fn is_timeout(err: &crate::anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<io::Error>().map(io::Error::kind),
+        Some(io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+    )
+}
+
+/// Open the URL in the system's browser.
+fn open_browser(url: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        process::Command::new("open")
+    } else {
+        process::Command::new("xdg-open")
+    };
+
+    log::debug!("Opening {}", url);
+    command
+        .arg(url)
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()?;
+
+    Ok(())
+}
+
+/// Address the watched command uses to reach the server.
+fn notify_address(server: &DevServer) -> SocketAddr {
+    let ip = match server.ip {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+
+    SocketAddr::new(ip, server.port)
+}
+
+/// Print a QR code of the URL in the terminal.
+#[cfg(feature = "qr")]
+fn print_qr_code(url: &str) {
+    use qrcode::{render::unicode::Dense1x2, QrCode};
+
+    match QrCode::new(url) {
+        // NOTE: the colors are inverted for the terminals with a dark background
+        Ok(code) => log::info!(
+            "Scan to open {}:\n{}",
+            url,
+            code.render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build()
+        ),
+        Err(err) => log::warn!("could not generate the QR code: {}", err),
+    }
+}
+
+/// Announce the server over mDNS.
+#[cfg(feature = "mdns")]
+fn announce_mdns(server: &DevServer, name: &str) -> Result<mdns_sd::ServiceDaemon> {
+    let host_name = mdns_host_name(name);
+    let name = host_name.trim_end_matches(".local");
+    let ips: Vec<IpAddr> = if server.ip.is_unspecified() {
+        interface_addresses()
+            .into_iter()
+            .filter(|ip| {
+                !ip.is_loopback() && (server.ip.is_ipv6() || server.dual_stack || ip.is_ipv4())
+            })
+            .collect()
+    } else {
+        vec![server.ip]
+    };
+    if ips.iter().all(|ip| ip.is_loopback()) {
+        log::warn!("the server is not reachable from the network, see `DevServer::address`");
+    }
+
+    let daemon = mdns_sd::ServiceDaemon::new().context("could not start the mDNS daemon")?;
+    let service = mdns_sd::ServiceInfo::new(
+        "_http._tcp.local.",
+        name,
+        &format!("{}.", host_name),
+        ips.as_slice(),
+        server.port,
+        None,
+    )
+    .context("invalid mDNS service")?;
+    daemon
+        .register(service)
+        .context("could not announce the server over mDNS")?;
+
+    log::info!(
+        "Announced over mDNS at: {}://{}.local:{}",
+        server.scheme(),
+        name,
+        server.port
+    );
+
+    Ok(daemon)
+}
+
+/// Host name announced over mDNS, like `my-app.local`, accepted by the host
+/// check.
+#[cfg(feature = "mdns")]
+fn mdns_host_name(name: &str) -> String {
+    format!(
+        "{}.local",
+        name.trim_end_matches('.').trim_end_matches(".local")
+    )
+}
+
+/// Add the options of the `[workspace.metadata.xtask-wasm.watch]` section of
+/// the workspace manifest to the watch.
+fn workspace_watch_config(mut watch: Watch) -> Result<Watch> {
+    let metadata = crate::metadata();
+    let config = match metadata.workspace_metadata.pointer("/xtask-wasm/watch") {
+        Some(config) => config
+            .as_object()
+            .context("`workspace.metadata.xtask-wasm.watch` must be a table")?,
+        None => return Ok(watch),
+    };
+
+    for (key, value) in config {
+        match key.as_str() {
+            "watch" | "ignore" => {
+                let paths = value
+                    .as_array()
+                    .and_then(|x| x.iter().map(|x| x.as_str()).collect::<Option<Vec<_>>>())
+                    .with_context(|| format!("watch option `{}` must be a list of paths", key))?;
+                for path in paths {
+                    let path = metadata.workspace_root.join(path);
+                    watch = if key == "watch" {
+                        watch.watch_path(path)
+                    } else {
+                        watch.exclude_path(path)
+                    };
+                }
+            }
+            "debounce" => {
+                let millis = value
+                    .as_u64()
+                    .context("watch option `debounce` must be a number of milliseconds")?;
+                watch = watch.debounce(Duration::from_millis(millis));
+            }
+            _ => log::warn!("unknown watch option `{}` in the workspace metadata", key),
+        }
+    }
+
+    Ok(watch)
+}
+
+/// Existing target directories of the workspace members, other than the target
+/// directory of the workspace, created when a member is built on its own.
+fn member_target_dirs() -> Vec<PathBuf> {
+    let metadata = crate::metadata();
+    metadata
+        .workspace_packages()
+        .iter()
+        .filter_map(|package| package.manifest_path.parent())
+        .map(|dir| dir.join("target").into_std_path_buf())
+        .filter(|path| path.is_dir() && path != metadata.target_directory.as_std_path())
+        .collect()
+}
+
+/// Send the output of the command through a pipe, writing its lines to
+/// stderr with a `[build]` prefix, and prefix the messages of the server with
+/// `[serve]`.
+#[cfg(unix)]
+fn prefix_output(command: &mut process::Command) -> Result<()> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    // SAFETY: the file descriptors are owned by the files right after being
+    //         created
+    let (reader, writer) = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error()).context("could not create a pipe");
+        }
+        for fd in fds {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1]))
+    };
+
+    // SAFETY: `isatty` only inspects the file descriptor
+    let colored = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+    if colored {
+        // NOTE: cargo disables its colors when the output is not a terminal
+        command.env("CARGO_TERM_COLOR", "always");
+    }
+    crate::serve_log::enable_prefix(colored);
+    command.stdout(writer.try_clone()?).stderr(writer);
+
+    thread::spawn(move || {
+        let prefix = if colored {
+            "\x1b[36m[build]\x1b[0m"
+        } else {
+            "[build]"
+        };
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+            eprintln!("{} {}", prefix, String::from_utf8_lossy(&line).trim_end());
+            line.clear();
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn prefix_output(_command: &mut process::Command) -> Result<()> {
+    log::warn!("prefixing the output of the command is only supported on Unix");
+    Ok(())
+}
+
+/// IP addresses of the network interfaces of the machine.
+#[cfg(unix)]
+fn interface_addresses() -> Vec<IpAddr> {
+    let mut addresses = Vec::new();
+    let mut interfaces = std::ptr::null_mut();
+
+    // SAFETY: the list is only read before being freed, the addresses are cast
+    //         according to their family
+    unsafe {
+        if libc::getifaddrs(&mut interfaces) != 0 {
+            return addresses;
+        }
+
+        let mut current = interfaces;
+        while let Some(interface) = current.as_ref() {
+            if let Some(address) = interface.ifa_addr.as_ref() {
+                match address.sa_family as libc::c_int {
+                    libc::AF_INET => {
+                        let address = &*(interface.ifa_addr as *const libc::sockaddr_in);
+                        addresses.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                            address.sin_addr.s_addr,
+                        ))));
+                    }
+                    libc::AF_INET6 => {
+                        let address = &*(interface.ifa_addr as *const libc::sockaddr_in6);
+                        addresses.push(IpAddr::V6(Ipv6Addr::from(address.sin6_addr.s6_addr)));
+                    }
+                    _ => {}
+                }
+            }
+            current = interface.ifa_next;
+        }
+
+        libc::freeifaddrs(interfaces);
+    }
+
+    addresses
+}
+
+/// IP address of the network interface used to reach the internet.
+#[cfg(not(unix))]
+fn interface_addresses() -> Vec<IpAddr> {
+    // NOTE: connecting a UDP socket doesn't send anything
+    std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9))?;
+            socket.local_addr()
+        })
+        .map(|address| vec![address.ip()])
+        .unwrap_or_default()
+}
+
+/// A connection to the server.
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+    // NOTE: shared so the live reload can keep writing to the connection
+    #[cfg(feature = "tls")]
+    Tls(Arc<Mutex<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>>),
+}
+
+impl Stream {
+    pub(crate) fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Ok(Stream::Tls(stream.clone())),
+        }
+    }
+
+    /// Set the read and write timeouts of the underlying socket.
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => {
+                let stream = stream.lock().expect("stream lock is not poisoned");
+                stream.sock.set_read_timeout(timeout)?;
+                stream.sock.set_write_timeout(timeout)
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream
+                .lock()
+                .expect("stream lock is not poisoned")
+                .read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream
+                .lock()
+                .expect("stream lock is not poisoned")
+                .write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.lock().expect("stream lock is not poisoned").flush(),
+        }
+    }
+}
+
+/// An HTTP request received by the server, parsed from the connection.
+#[derive(Clone)]
+pub(crate) struct ParsedRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    /// Path and query string, as sent by the client.
+    pub(crate) target: String,
+    version: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl ParsedRequest {
+    pub(crate) fn new(method: &str, target: &str, version: &str) -> ParsedRequest {
+        let path = target
+            .split_once('?')
+            .map(|(prefix, _suffix)| prefix)
+            .unwrap_or(target);
+
+        ParsedRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Read the request line and the headers.
+    ///
+    /// Returns `None` if the connection has been closed.
+    fn read(mut reader: impl BufRead) -> Result<Option<ParsedRequest>> {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let mut parts = line.split_whitespace();
+        let method = parts.next().context("Could not find method in request")?;
+        let target = parts.next().context("Could not find path in request")?;
+        let version = parts.next().unwrap_or("HTTP/1.0");
+        let mut request = ParsedRequest::new(method, target, version);
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => request
+                    .headers
+                    .push((name.trim().to_string(), value.trim().to_string())),
+                None => break,
+            }
+        }
+
+        Ok(Some(request))
+    }
+
+    /// Decode the parameters of the query string.
+    fn query(&self) -> Vec<(String, String)> {
+        self.target
+            .split_once('?')
+            .map(|(_, query)| query)
+            .unwrap_or_default()
+            .split('&')
+            .filter(|x| !x.is_empty())
+            .map(|parameter| {
+                let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+                (
+                    percent_decode(&name.replace('+', " ")),
+                    percent_decode(&value.replace('+', " ")),
+                )
+            })
+            .collect()
+    }
+
+    /// Get the value of a header, the name is case-insensitive.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the connection should be kept open after the response.
+    ///
+    /// Only HTTP/1.1 connections are kept alive, unless the client asks to
+    /// close them.
+    fn keep_alive(&self) -> bool {
+        self.version == "HTTP/1.1"
+            && !matches!(self.header("connection"), Some(x) if x.eq_ignore_ascii_case("close"))
+    }
+}
+
+/// Pass a request through the middlewares and the default request handler.
+pub(crate) fn handle_request(
+    stream: &mut dyn Write,
+    request: &ParsedRequest,
+    server: &DevServer,
+    dist_dir_path: &Path,
+) -> Result<()> {
+    let start = Instant::now();
+
+    let mut throttled;
+    let stream: &mut dyn Write = match server.throttle {
+        Some(throttle) => {
+            thread::sleep(throttle.latency);
+            match throttle.bytes_per_sec {
+                Some(bytes_per_sec) if bytes_per_sec > 0 => {
+                    throttled = Throttled {
+                        inner: stream,
+                        bytes_per_sec,
+                        start: Instant::now(),
+                        written: 0,
+                    };
+                    &mut throttled
+                }
+                _ => stream,
+            }
+        }
+        None => stream,
+    };
+    let mut recorder = access_log::Recorder::new(stream);
+
+    let result = if host_allowed(server, request) {
+        Next {
+            middlewares: &server.middlewares,
+            server,
+        }
+        .run(Request {
+            stream: &mut recorder,
+            method: &request.method,
+            path: &request.path,
+            query: request.query(),
+            dist_dir_path,
+            parsed: request,
+        })
+    } else {
+        reject_host(&mut recorder, request)
+    };
+    let result = match result {
+        Err(err) if recorder.status().is_none() => {
+            log::error!("an error occurred: {:#}", err);
+            write_error(&mut recorder, request, server, dist_dir_path, 500)
+        }
+        result => result,
+    };
+
+    access_log::log(
+        &server.access_log_format,
+        server.access_log_file.as_deref(),
+        request,
+        &recorder,
+        result.is_err(),
+        start.elapsed(),
+    );
+    server
+        .metrics
+        .record(&request.path, recorder.bytes(), start.elapsed());
+
+    result
+}
+
+/// A request received by the development server.
+#[non_exhaustive]
+pub struct Request<'a> {
+    /// Connection to the client, where the response is written.
+    pub stream: &'a mut dyn Write,
+    /// Method of the request, like `GET` or `HEAD`.
+    pub method: &'a str,
+    /// Requested path, without the query string.
+    pub path: &'a str,
+    /// Decoded parameters of the query string, in order.
+    pub query: Vec<(String, String)>,
+    /// Path of the served directory.
+    pub dist_dir_path: &'a Path,
+    parsed: &'a ParsedRequest,
+}
+
+impl Request<'_> {
+    /// Get the value of a header, the name is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.parsed.header(name)
+    }
+
+    /// Headers of the request, in the order they were sent.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.parsed.headers
+    }
+
+    /// Get the value of a query parameter.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Body of the request, empty if it has none.
+    ///
+    /// ```rust,no_run
+    /// # use std::io::Read;
+    /// # use xtask_wasm::DevServer;
+    /// DevServer::default().route("/echo", |request| {
+    ///     let mut body = String::new();
+    ///     request.body().read_to_string(&mut body)?;
+    ///     write!(
+    ///         request.stream,
+    ///         "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+    ///         body.len(),
+    ///         body,
+    ///     )?;
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn body(&self) -> &[u8] {
+        &self.parsed.body
+    }
+}
+
+/// Response written by a custom handler, sent with the chunked transfer
+/// encoding.
+///
+/// The status line and the headers are sent with the first chunk. Each write
+/// is sent as a chunk, which makes it possible to stream generated content
+/// like [server-sent events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events).
+/// The response is finished when dropped.
+///
+/// ```rust,no_run
+/// # use std::{thread, time::Duration};
+/// # use xtask_wasm::{DevServer, Response};
+/// DevServer::default().route("/events", |request| {
+///     let mut response = Response::new(request.stream)
+///         .header("Content-Type", "text/event-stream")
+///         .header("Cache-Control", "no-store");
+///     for i in 0..10 {
+///         response.write_chunk(format!("data: {}\n\n", i).as_bytes())?;
+///         thread::sleep(Duration::from_secs(1));
+///     }
+///     response.finish()?;
+///     Ok(())
+/// });
+/// ```
+pub struct Response<'a> {
+    stream: &'a mut dyn Write,
+    status: u16,
+    headers: Vec<(String, String)>,
+    head_sent: bool,
+    finished: bool,
+}
+
+impl<'a> Response<'a> {
+    /// Create a `200 OK` response written to the given stream.
+    pub fn new(stream: &'a mut dyn Write) -> Response<'a> {
+        Response {
+            stream,
+            status: 200,
+            headers: Vec::new(),
+            head_sent: false,
+            finished: false,
+        }
+    }
+
+    /// Set the status code of the response.
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Add a header to the response.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Send a chunk of the body, flushing it to the client.
+    ///
+    /// Empty chunks are ignored as they would end the response.
+    pub fn write_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        self.send_head()?;
+        if !data.is_empty() {
+            write!(self.stream, "{:x}\r\n", data.len())?;
+            self.stream.write_all(data)?;
+            self.stream.write_all(b"\r\n")?;
+        }
+        self.stream.flush()
+    }
+
+    /// End the response.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.end()
+    }
+
+    fn send_head(&mut self) -> io::Result<()> {
+        if self.head_sent {
+            return Ok(());
+        }
+        self.head_sent = true;
+
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nTransfer-Encoding: chunked\r\n",
+            self.status,
+            reason_phrase(self.status),
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        self.stream.write_all(head.as_bytes())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        self.send_head()?;
+        self.stream.write_all(b"0\r\n\r\n")?;
+        self.stream.flush()
+    }
+}
+
+impl Write for Response<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_chunk(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Drop for Response<'_> {
+    fn drop(&mut self) {
+        let _ = self.end();
+    }
+}
+
+/// The rest of the middlewares of a [`DevServer`], followed by the routes and
+/// the default request handler.
+pub struct Next<'a> {
+    middlewares: &'a [Middleware],
+    server: &'a DevServer,
+}
+
+impl Next<'_> {
+    /// Pass the request to the next middleware, to the matching route or to
+    /// the default request handler serving the files.
+    pub fn run(self, request: Request) -> Result<()> {
+        match self.middlewares.split_first() {
+            Some((middleware, middlewares)) => (middleware.0)(
+                request,
+                Next {
+                    middlewares,
+                    server: self.server,
+                },
+            ),
+            None => match find_route(&self.server.routes, request.path) {
+                Some(route) => (route.handler)(request),
+                None => default_request_handler(request, self.server),
+            },
+        }
+    }
+}
+
+/// Result of a handler added with [`DevServer::request_handler`].
+pub enum HandlerResult<'a> {
+    /// The response has been written.
+    Handled,
+    /// The request is passed to the next handlers.
+    NotHandled(Request<'a>),
+}
+
+type MiddlewareFn = dyn Fn(Request, Next) -> Result<()> + Send + Sync;
+
+#[derive(Clone)]
+struct Middleware(Arc<MiddlewareFn>);
+
+impl fmt::Debug for Middleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Middleware")
+    }
+}
+
+type RouteFn = dyn Fn(Request) -> Result<()> + Send + Sync;
+
+#[derive(Clone)]
+struct Route {
+    path: String,
+    prefix: bool,
+    handler: Arc<RouteFn>,
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("path", &self.path)
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+/// Find the route of a path: an exact route first, then the longest matching
+/// prefix.
+fn find_route<'a>(routes: &'a [Route], path: &str) -> Option<&'a Route> {
+    routes
+        .iter()
+        .find(|route| !route.prefix && route.path == path)
+        .or_else(|| {
+            routes
+                .iter()
+                .filter(|route| route.prefix && strip_path_prefix(path, &route.path).is_some())
+                .max_by_key(|route| route.path.len())
+        })
+}
+
+fn default_request_handler(request: Request, server: &DevServer) -> Result<()> {
+    let stream = request.stream;
+    let dist_dir_path = request.dist_dir_path;
+    let request = request.parsed;
+    let query = request.target.split_once('?').map(|(_, query)| query);
+
+    if let Some((_, to, status)) = server
+        .redirects
+        .iter()
+        .rev()
+        .find(|(from, _, _)| *from == request.path)
+    {
+        let location = match query {
+            Some(query) if !to.contains('?') => format!("{}?{}", to, query),
+            _ => to.to_string(),
+        };
+        log::debug!("--> {} ({})", location, status);
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 {} {}\r\nLocation: {}\r\nContent-Length: 0\r\n{}\r\n",
+                    status,
+                    reason_phrase(*status),
+                    location,
+                    extra_headers(server),
+                )
+                .as_bytes(),
+            )
+            .context("cannot write response")?;
+        return Ok(());
+    }
+
+    let rewritten;
+    let request = match server
+        .rewrites
+        .iter()
+        .filter_map(|(from, to)| {
+            strip_path_prefix(&request.path, from).map(|rest| (from.len(), to, rest))
+        })
+        .max_by_key(|(len, _, _)| *len)
+    {
+        Some((_, to, rest)) => {
+            let mut path = format!("{}{}", to.trim_end_matches('/'), rest);
+            if !path.starts_with('/') {
+                path.insert(0, '/');
+            }
+            log::debug!("--> {} (rewritten)", path);
+            rewritten = ParsedRequest {
+                target: match query {
+                    Some(query) => format!("{}?{}", path, query),
+                    None => path.clone(),
+                },
+                path,
+                ..request.clone()
+            };
+            &rewritten
+        }
+        None => request,
+    };
+    let requested_path = request.path.as_str();
+
+    if let Some((fixture, status)) = server
+        .mock_api
+        .as_deref()
+        .and_then(|root| mock_api::find(root, &request.method, &percent_decode(requested_path)))
+    {
+        return mock_api::respond(
+            stream,
+            &fixture,
+            status,
+            &extra_headers(server),
+            request.method == "HEAD",
+        );
+    }
+
+    if let Some((prefix, backend)) = server
+        .proxies
+        .iter()
+        .filter(|(prefix, _)| strip_path_prefix(requested_path, prefix).is_some())
+        .max_by_key(|(prefix, _)| prefix.len())
+    {
+        return forward_to_backend(stream, request, server, prefix, backend);
+    }
+
+    let (root, rel_path) = std::iter::once(("/", dist_dir_path))
+        .chain(
+            server
+                .mounts
+                .iter()
+                .map(|(prefix, path)| (prefix.as_str(), path.as_path())),
+        )
+        .filter_map(|(prefix, path)| {
+            strip_path_prefix(requested_path, prefix).map(|rest| (prefix.len(), path, rest))
+        })
+        .max_by_key(|(len, _, _)| *len)
+        .map(|(_, path, rest)| (path, rest))
+        .unwrap_or((dist_dir_path, requested_path));
+
+    let rel_path = percent_decode(rel_path.trim_matches('/'));
+    let rel_path = Path::new(&rel_path);
+    let mut full_path = root.join(rel_path);
+
+    if !is_relative_path(rel_path) || !is_within(&full_path, &[root], server) {
+        log::warn!("--> {} (403 FORBIDDEN)", full_path.display());
+        return write_error(stream, request, server, dist_dir_path, 403);
+    }
+
+    if full_path.is_dir() {
+        match server
+            .index_files
+            .iter()
+            .map(|name| full_path.join(name))
+            .find(|path| path.is_file())
+        {
+            Some(index_path) => full_path = index_path,
+            None if server.directory_listing => {
+                return directory_listing(stream, &full_path, request, server);
+            }
+            None => bail!("no index file in {}", full_path.display()),
+        }
+    }
+
+    if server.clean_urls && !full_path.is_file() && rel_path.extension().is_none() {
+        let html_path = full_path.with_extension("html");
+        if html_path.is_file() {
+            full_path = html_path;
+        }
+    }
+
+    if server.spa
+        && !full_path.is_file()
+        && rel_path.extension().is_none()
+        && matches!(request.header("accept"), Some(x) if x.contains("text/html"))
+    {
+        if let Some(index_path) = server
+            .index_files
+            .iter()
+            .map(|name| dist_dir_path.join(name))
+            .find(|path| path.is_file())
+        {
+            full_path = index_path;
+        }
+    }
 
-    if let Some(path) = not_found_path {
+    if let Some(path) = &server.not_found_path {
         if !full_path.is_file() {
-            full_path = dist_dir_path.as_ref().join(path);
+            full_path = dist_dir_path.join(path);
         }
     }
 
+    if full_path.is_file() && !is_within(&full_path, &[root, dist_dir_path], server) {
+        log::warn!("--> {} (403 FORBIDDEN)", full_path.display());
+        return write_error(stream, request, server, dist_dir_path, 403);
+    }
+
     if full_path.is_file() {
         log::debug!("--> {}", full_path.display());
         let full_path_extension = Utf8Path::from_path(&full_path)
             .context("request path contains non-utf8 characters")?
             .extension();
 
-        let content_type = match full_path_extension {
-            Some("html") => "text/html;charset=utf-8",
-            Some("css") => "text/css;charset=utf-8",
-            Some("js") => "application/javascript",
-            Some("wasm") => "application/wasm",
-            _ => "application/octet-stream",
+        let content_type = full_path_extension
+            .and_then(|extension| {
+                server
+                    .mime_types
+                    .iter()
+                    .rev()
+                    .find(|(x, _)| x.trim_start_matches('.').eq_ignore_ascii_case(extension))
+                    .map(|(_, content_type)| content_type.as_str())
+            })
+            .unwrap_or_else(|| content_type(full_path_extension.unwrap_or_default()));
+
+        let inject_reload = server.reload && full_path_extension == Some("html");
+        let (served_file, content_encoding) = match precompressed(&full_path, request) {
+            Some((path, encoding)) if !inject_reload => (path, Some(encoding)),
+            _ => (full_path.clone(), None),
+        };
+
+        let cached = match &server.file_cache {
+            Some(file_cache) => file_cache.get(&served_file)?,
+            None => None,
+        };
+        let (modified, len) = match &cached {
+            Some(file) => (file.modified, file.content.len() as u64),
+            None => {
+                let metadata = served_file.metadata()?;
+                (modified_secs(&metadata)?, metadata.len())
+            }
+        };
+        let etag = match content_encoding {
+            Some(encoding) => format!("\"{:x}-{:x}-{}\"", modified, len, encoding),
+            None => format!("\"{:x}-{:x}\"", modified, len),
+        };
+        let file_name = full_path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default();
+        let cache_control = server
+            .cache_control_rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| {
+                if pattern.contains('/') {
+                    wildcard_match(pattern, &request.path)
+                } else {
+                    wildcard_match(pattern, file_name)
+                }
+            })
+            .map_or(server.cache_control.as_str(), |(_, value)| value.as_str());
+        let mut validators = format!(
+            "ETag: {}\r\nLast-Modified: {}\r\nCache-Control: {}\r\nVary: Accept-Encoding\r\n",
+            etag,
+            http_date(modified),
+            cache_control,
+        );
+        if let Some(encoding) = content_encoding {
+            log::debug!("--> {}", served_file.display());
+            validators.push_str(&format!("Content-Encoding: {}\r\n", encoding));
+        }
+
+        let not_modified = match request.header("if-none-match") {
+            Some(tags) => tags
+                .split(',')
+                .map(|x| x.trim().trim_start_matches("W/"))
+                .any(|x| x == etag || x == "*"),
+            None => matches!(
+                request.header("if-modified-since").and_then(parse_http_date),
+                Some(since) if modified <= since
+            ),
+        };
+
+        if not_modified {
+            log::debug!("--> {} (304 NOT MODIFIED)", full_path.display());
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 304 NOT MODIFIED\r\n{}{}\r\n",
+                        validators,
+                        extra_headers(server)
+                    )
+                    .as_bytes(),
+                )
+                .context("cannot write response")?;
+            return Ok(());
+        }
+
+        // NOTE: the other files are streamed from the disk, without being
+        // loaded in memory
+        let mut content = match &cached {
+            Some(file) => Some(Cow::Borrowed(file.content.as_slice())),
+            None if inject_reload => Some(Cow::Owned(fs::read(&served_file)?)),
+            None => None,
+        };
+        if let (true, Some(bytes)) = (inject_reload, content.take()) {
+            let mut html =
+                String::from_utf8(bytes.into_owned()).context("HTML file is not valid UTF-8")?;
+            live_reload::inject_client(&mut html, request.header("host"));
+            content = Some(Cow::Owned(html.into_bytes()));
+        }
+        let content_len = match &content {
+            Some(content) => content.len(),
+            None => len as usize,
+        };
+
+        let range = match request.header("range") {
+            Some(_) if matches!(request.header("if-range"), Some(x) if x != etag) => None,
+            Some(range) => match parse_range(range, content_len) {
+                Some(range) => range,
+                None => {
+                    log::error!("--> {} (416 RANGE NOT SATISFIABLE)", full_path.display());
+                    stream
+                        .write_all(
+                            format!(
+                                "HTTP/1.1 416 RANGE NOT SATISFIABLE\r\nContent-Length: 0\r\n\
+                                Content-Range: bytes */{}\r\n{}\r\n",
+                                content_len,
+                                extra_headers(server),
+                            )
+                            .as_bytes(),
+                        )
+                        .context("cannot write response")?;
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+
+        let (status, content_range, start, body_len) = match range {
+            Some((start, end)) => (
+                "206 PARTIAL CONTENT",
+                format!("Content-Range: bytes {}-{}/{}\r\n", start, end, content_len),
+                start,
+                end - start + 1,
+            ),
+            None => ("200 OK", String::new(), 0, content_len),
         };
 
         stream
-            .write(
+            .write_all(
                 format!(
-                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: {}\r\n\r\n",
-                    full_path.metadata()?.len(),
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: {}\r\n\
+                    Accept-Ranges: bytes\r\n{}{}{}\r\n",
+                    status,
+                    body_len,
                     content_type,
+                    content_range,
+                    validators,
+                    extra_headers(server),
                 )
                 .as_bytes(),
             )
             .context("cannot write response")?;
 
-        std::io::copy(&mut fs::File::open(&full_path)?, stream)?;
+        if request.method != "HEAD" {
+            match &content {
+                Some(content) => stream.write_all(&content[start..start + body_len])?,
+                None => send_file(stream, &served_file, start as u64, body_len as u64)?,
+            }
+        }
     } else {
         log::error!("--> {} (404 NOT FOUND)", full_path.display());
+        write_error(stream, request, server, dist_dir_path, 404)?;
+    }
+
+    Ok(())
+}
+
+/// Write an error response, using the error page of the status if the client
+/// accepts HTML or a problem details body if it accepts JSON.
+fn write_error(
+    stream: &mut dyn Write,
+    request: &ParsedRequest,
+    server: &DevServer,
+    dist_dir_path: &Path,
+    status: u16,
+) -> Result<()> {
+    let accept = request.header("accept").unwrap_or_default();
+    let error_page = server
+        .error_pages
+        .iter()
+        .rev()
+        .find(|(x, _)| *x == status)
+        .map(|(_, path)| dist_dir_path.join(path))
+        .filter(|path| path.is_file());
+
+    let (content_type, body) = match error_page {
+        Some(path) if accept.contains("text/html") => {
+            log::debug!("--> {}", path.display());
+            let mut html = fs::read_to_string(&path)
+                .with_context(|| format!("could not read `{}`", path.display()))?;
+            if server.reload {
+                live_reload::inject_client(&mut html, request.header("host"));
+            }
+            ("text/html; charset=utf-8", html)
+        }
+        _ if accept.contains("json") => {
+            let title = reason_phrase(status)
+                .split(' ')
+                .map(|word| {
+                    let (first, rest) = word.split_at(word.len().min(1));
+                    first.to_string() + &rest.to_lowercase()
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            let problem = serde_json::json!({
+                "type": "about:blank",
+                "title": title,
+                "status": status,
+                "instance": request.path,
+            });
+            ("application/problem+json", problem.to_string())
+        }
+        _ => ("", String::new()),
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+        status,
+        reason_phrase(status),
+        body.len(),
+    );
+    if !content_type.is_empty() {
+        head.push_str(&format!("Content-Type: {}\r\n", content_type));
+    }
+    stream
+        .write_all(format!("{}{}\r\n", head, extra_headers(server)).as_bytes())
+        .context("cannot write response")?;
+    if request.method != "HEAD" {
         stream
-            .write("HTTP/1.1 404 NOT FOUND\r\n\r\n".as_bytes())
+            .write_all(body.as_bytes())
             .context("cannot write response")?;
     }
 
     Ok(())
 }
+
+/// Whether a path only contains normal components, that can't escape the
+/// directory it is joined to.
+pub(crate) fn is_relative_path(path: &Path) -> bool {
+    path.components()
+        .all(|x| matches!(x, Component::Normal(_) | Component::CurDir))
+}
+
+/// Whether the canonical path of an existing file is in one of the given
+/// directories, unless the symbolic links are followed.
+fn is_within(path: &Path, roots: &[&Path], server: &DevServer) -> bool {
+    if server.follow_symlinks {
+        return true;
+    }
+
+    match path.canonicalize() {
+        Ok(path) => roots
+            .iter()
+            .any(|root| matches!(root.canonicalize(), Ok(root) if path.starts_with(&root))),
+        Err(_) => true,
+    }
+}
+
+/// Files of the served directory kept in memory, see
+/// [`DevServer::cache_files`].
+pub(crate) struct FileCache {
+    root: PathBuf,
+    files: Mutex<HashMap<PathBuf, Arc<CachedFile>>>,
+}
+
+/// Content of a file read by the [`FileCache`].
+struct CachedFile {
+    modified: u64,
+    content: Vec<u8>,
+}
+
+impl FileCache {
+    fn new(root: &Path) -> FileCache {
+        FileCache {
+            root: root.to_path_buf(),
+            files: Default::default(),
+        }
+    }
+
+    /// Get a file, reading it on the first call. Returns `None` if the file is
+    /// not in the served directory.
+    fn get(&self, path: &Path) -> Result<Option<Arc<CachedFile>>> {
+        if !path.starts_with(&self.root) {
+            return Ok(None);
+        }
+        if let Some(file) = self
+            .files
+            .lock()
+            .expect("cache lock is not poisoned")
+            .get(path)
+        {
+            return Ok(Some(file.clone()));
+        }
+
+        let metadata = path.metadata()?;
+        let file = Arc::new(CachedFile {
+            modified: modified_secs(&metadata)?,
+            content: fs::read(path)?,
+        });
+        self.files
+            .lock()
+            .expect("cache lock is not poisoned")
+            .insert(path.to_path_buf(), file.clone());
+
+        Ok(Some(file))
+    }
+
+    /// Forget the cached files, after a change of the served directory.
+    pub(crate) fn clear(&self) {
+        let mut files = self.files.lock().expect("cache lock is not poisoned");
+        if !files.is_empty() {
+            log::debug!("Clearing {} cached file(s)", files.len());
+            files.clear();
+        }
+    }
+}
+
+impl fmt::Debug for FileCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileCache")
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Size of the buffer used to send the files, large enough for the
+/// multi-megabyte WebAssembly binaries to be sent in a few writes.
+const SEND_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Write a part of a file to the stream.
+fn send_file(stream: &mut dyn Write, path: &Path, start: u64, len: u64) -> Result<()> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("could not open `{}`", path.display()))?;
+    file.seek(io::SeekFrom::Start(start))?;
+    let mut file = file.take(len);
+
+    let mut buffer = vec![0; SEND_BUFFER_SIZE.min(len as usize)];
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err).context("could not read file"),
+        };
+        stream.write_all(&buffer[..read])?;
+    }
+}
+
+/// Modification time of a file, in seconds since the Unix epoch.
+fn modified_secs(metadata: &fs::Metadata) -> Result<u64> {
+    Ok(metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs())
+}
+
+/// Whether a text matches a pattern where `*` matches any sequence of
+/// characters.
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    // NOTE: position of the last `*` and of the text it matches up to
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(x) if *x == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == b'*')
+}
+
+/// Get the rest of the path if it starts with the given prefix, which must
+/// match whole segments.
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.strip_prefix(prefix.trim_end_matches('/'))
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Forward a request to a backend and copy its response.
+fn forward_to_backend(
+    stream: &mut dyn Write,
+    request: &ParsedRequest,
+    server: &DevServer,
+    prefix: &str,
+    backend: &str,
+) -> Result<()> {
+    let backend_url = backend
+        .strip_prefix("http://")
+        .with_context(|| format!("unsupported proxy URL `{}`", backend))?;
+    let (authority, base_path) = match backend_url.find('/') {
+        Some(index) => backend_url.split_at(index),
+        None => (backend_url, ""),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let target = format!(
+        "{}{}",
+        base_path.trim_end_matches('/'),
+        &request.target[prefix.trim_end_matches('/').len()..]
+    );
+    log::debug!("--> {}{}", backend.trim_end_matches('/'), target);
+
+    let timeout = server.header_timeout.or(server.timeout);
+    let mut backend_stream = connect_backend(&address, timeout)
+        .with_context(|| format!("could not connect to `{}`", address))?;
+    backend_stream.set_read_timeout(timeout)?;
+    backend_stream.set_write_timeout(timeout)?;
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n",
+        request.method, target, authority
+    );
+    for (name, value) in &request.headers {
+        if !name.eq_ignore_ascii_case("host") && !name.eq_ignore_ascii_case("connection") {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    backend_stream.write_all(head.as_bytes())?;
+    backend_stream.write_all(&request.body)?;
+
+    // NOTE: the backend closes the connection after the response so it can be
+    //       copied as is
+    io::copy(&mut backend_stream, stream).context("could not forward the response")?;
+
+    Ok(())
+}
+
+/// Connect to the first reachable address of a backend.
+fn connect_backend(address: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address found");
+    for socket_address in address.to_socket_addrs()? {
+        let result = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&socket_address, timeout),
+            None => TcpStream::connect(socket_address),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = err,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Check the `Host` header of a request against the allowed hosts.
+pub(crate) fn host_allowed(server: &DevServer, request: &ParsedRequest) -> bool {
+    let host = match request.header("host") {
+        Some(host) if !server.disable_host_check => host,
+        _ => return true,
+    };
+
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None if host.matches(':').count() > 1 => host,
+        None => host.split(':').next().unwrap_or_default(),
+    }
+    .trim_end_matches('.')
+    .to_ascii_lowercase();
+
+    #[cfg(feature = "mdns")]
+    if matches!(&server.mdns, Some(mdns) if name == mdns_host_name(mdns).to_ascii_lowercase()) {
+        return true;
+    }
+
+    name == "localhost"
+        || name.ends_with(".localhost")
+        || name.parse::<IpAddr>().is_ok()
+        || server.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            match allowed.strip_prefix('.') {
+                Some(domain) => name == domain || name.ends_with(&allowed),
+                None => name == allowed,
+            }
+        })
+}
+
+/// Answer a request whose host is not allowed.
+pub(crate) fn reject_host(stream: &mut dyn Write, request: &ParsedRequest) -> Result<()> {
+    log::warn!(
+        "rejected a request for the host `{}`, see `DevServer::allowed_host`",
+        request.header("host").unwrap_or_default()
+    );
+    stream
+        .write_all(b"HTTP/1.1 403 FORBIDDEN\r\nContent-Length: 0\r\n\r\n")
+        .context("cannot write response")
+}
+
+/// Write an HTML page listing the content of a directory.
+fn directory_listing(
+    stream: &mut dyn Write,
+    dir: &Path,
+    request: &ParsedRequest,
+    server: &DevServer,
+) -> Result<()> {
+    let requested_path = request.path.as_str();
+    log::debug!("--> {} (directory listing)", dir.display());
+
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("could not read `{}`", dir.display()))?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((!is_dir, entry.file_name().to_string_lossy().into_owned()))
+        })
+        .collect::<Vec<_>>();
+    // NOTE: directories first
+    entries.sort();
+
+    let base = requested_path.trim_end_matches('/');
+    let mut items = String::new();
+    if !base.is_empty() {
+        items.push_str(&format!(
+            "<li><a href=\"{}/\">../</a></li>\n",
+            escape_html(&base[..base.rfind('/').unwrap_or(0)])
+        ));
+    }
+    for (is_file, name) in &entries {
+        let slash = if *is_file { "" } else { "/" };
+        items.push_str(&format!(
+            "<li><a href=\"{}/{}{}\">{}{}</a></li>\n",
+            escape_html(base),
+            escape_html(&percent_encode(name)),
+            slash,
+            escape_html(name),
+            slash,
+        ));
+    }
+
+    let title = escape_html(&percent_decode(if base.is_empty() {
+        "/"
+    } else {
+        requested_path
+    }));
+    let body = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\">\
+        <title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n\
+        <ul>\n{1}</ul>\n</body>\n</html>\n",
+        title, items,
+    );
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/html;charset=utf-8\r\n\
+                Cache-Control: no-cache\r\n{}\r\n",
+                body.len(),
+                extra_headers(server),
+            )
+            .as_bytes(),
+        )
+        .context("cannot write response")?;
+    if request.method != "HEAD" {
+        stream.write_all(body.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Escape the characters of a text inserted in HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Decode the percent-encoded characters of a path.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Percent-encode a path segment.
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (x as char).to_string()
+            }
+            _ => format!("%{:02X}", x),
+        })
+        .collect()
+}
+
+/// Built-in content type of a file extension.
+fn content_type(extension: &str) -> &'static str {
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html;charset=utf-8",
+        "css" => "text/css;charset=utf-8",
+        "js" | "mjs" => "application/javascript",
+        "wasm" => "application/wasm",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain;charset=utf-8",
+        "md" => "text/markdown;charset=utf-8",
+        "csv" => "text/csv;charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "glb" => "model/gltf-binary",
+        "gltf" => "model/gltf+json",
+        _ => "application/octet-stream",
+    }
+}
+
+fn extra_headers(server: &DevServer) -> String {
+    let mut headers = String::new();
+
+    if server.cross_origin_isolation {
+        headers.push_str("Cross-Origin-Opener-Policy: same-origin\r\n");
+        headers.push_str("Cross-Origin-Embedder-Policy: require-corp\r\n");
+    }
+
+    for (name, value) in &server.headers {
+        headers.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    headers
+}
+
+/// Find a precompressed sibling of `path` (`.br` or `.gz`) accepted by the
+/// client, along with its content encoding.
+fn precompressed(path: &Path, request: &ParsedRequest) -> Option<(PathBuf, &'static str)> {
+    let accepted: Vec<&str> = request
+        .header("accept-encoding")?
+        .split(',')
+        .filter(|x| !x.replace(' ', "").ends_with(";q=0"))
+        .map(|x| x.split(';').next().unwrap_or_default().trim())
+        .collect();
+
+    [("br", "br"), ("gzip", "gz")]
+        .iter()
+        .filter(|(encoding, _)| accepted.contains(encoding))
+        .map(|(encoding, extension)| {
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(".");
+            sibling.push(extension);
+            (PathBuf::from(sibling), *encoding)
+        })
+        .find(|(sibling, _)| sibling.is_file())
+}
+
+/// Parse a `Range` header with a single byte range to the first and last
+/// positions of the range.
+///
+/// Returns `Some(None)` if the range is not supported, so the whole content is
+/// sent, and `None` if it cannot be satisfied.
+fn parse_range(range: &str, len: usize) -> Option<Option<(usize, usize)>> {
+    let range = match range.trim().strip_prefix("bytes=") {
+        Some(range) if !range.contains(',') => range,
+        _ => return Some(None),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Some(None),
+    };
+
+    let (start, end) = match (start.trim().parse::<usize>(), end.trim().parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.trim().is_empty() => (start, len.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.trim().is_empty() && suffix > 0 => {
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        _ => return Some(None),
+    };
+
+    if start >= len {
+        None
+    } else {
+        Some(Some((start, end)))
+    }
+}
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Reason phrase of the common status codes.
+pub(crate) fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        101 => "SWITCHING PROTOCOLS",
+        200 => "OK",
+        201 => "CREATED",
+        202 => "ACCEPTED",
+        204 => "NO CONTENT",
+        206 => "PARTIAL CONTENT",
+        301 => "MOVED PERMANENTLY",
+        302 => "FOUND",
+        303 => "SEE OTHER",
+        304 => "NOT MODIFIED",
+        307 => "TEMPORARY REDIRECT",
+        308 => "PERMANENT REDIRECT",
+        400 => "BAD REQUEST",
+        401 => "UNAUTHORIZED",
+        403 => "FORBIDDEN",
+        404 => "NOT FOUND",
+        405 => "METHOD NOT ALLOWED",
+        409 => "CONFLICT",
+        413 => "PAYLOAD TOO LARGE",
+        422 => "UNPROCESSABLE ENTITY",
+        429 => "TOO MANY REQUESTS",
+        431 => "REQUEST HEADER FIELDS TOO LARGE",
+        500 => "INTERNAL SERVER ERROR",
+        502 => "BAD GATEWAY",
+        503 => "SERVICE UNAVAILABLE",
+        504 => "GATEWAY TIMEOUT",
+        _ => "",
+    }
+}
+
+/// Format a UNIX timestamp as an HTTP date, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub(crate) fn http_date(timestamp: u64) -> String {
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+
+    // NOTE: conversion from days to civil date by Howard Hinnant
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    )
+}
+
+/// Parse an HTTP date formatted by [`http_date`] to a UNIX timestamp.
+fn parse_http_date(date: &str) -> Option<u64> {
+    let mut parts = date.split_whitespace().skip(1);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|x| *x == month_name)? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|x| x.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+
+    // NOTE: conversion from civil date to days by Howard Hinnant
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
+
+    Some(days * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_date_format() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parse_http_date_round_trip() {
+        for timestamp in [0, 784111777, 951782400, 1700000000] {
+            assert_eq!(parse_http_date(&http_date(timestamp)), Some(timestamp));
+        }
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert_eq!(parse_http_date(""), None);
+    }
+
+    #[test]
+    fn parse_range_bounds() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Some((0, 99))));
+        assert_eq!(parse_range("bytes=500-", 1000), Some(Some((500, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Some((900, 999))));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Some(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some(Some((0, 999))));
+    }
+
+    #[test]
+    fn parse_range_unsupported_or_unsatisfiable() {
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Some(None));
+        assert_eq!(parse_range("items=0-1", 1000), Some(None));
+        assert_eq!(parse_range("bytes=5-1", 1000), Some(None));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(None));
+        assert_eq!(parse_range("bytes=a-b", 1000), Some(None));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+
+    #[test]
+    fn wildcard_match_patterns() {
+        assert!(wildcard_match("*.example.com", "app.example.com"));
+        assert!(!wildcard_match("*.example.com", "example.com"));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+        assert!(wildcard_match("a*c", "abcbc"));
+        assert!(!wildcard_match("a*c", "ab"));
+        assert!(wildcard_match("a*", "a"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("", ""));
+        assert!(!wildcard_match("abc", "abcd"));
+        assert!(!wildcard_match("abcd", "abc"));
+    }
+
+    fn throttled_response(request: ParsedRequest) -> String {
+        let dir = std::env::temp_dir().join(format!("xtask-wasm-throttle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("file.txt"), "hello world").unwrap();
+
+        let server = DevServer::default().throttle(ThrottleConfig {
+            latency: Duration::ZERO,
+            bytes_per_sec: Some(500),
+        });
+        let mut response = Vec::new();
+        handle_request(&mut response, &request, &server, &dir).unwrap();
+
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn throttled_response_is_complete() {
+        let request = ParsedRequest::new("GET", "/file.txt", "HTTP/1.1");
+        let response = throttled_response(request);
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(
+            response.contains("\r\nContent-Length: 11\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("\r\n\r\nhello world"), "{}", response);
+    }
+
+    #[test]
+    fn throttled_range_not_satisfiable_is_complete() {
+        let mut request = ParsedRequest::new("GET", "/file.txt", "HTTP/1.1");
+        request
+            .headers
+            .push(("range".to_string(), "bytes=100-".to_string()));
+        let response = throttled_response(request);
+
+        assert!(
+            response.starts_with("HTTP/1.1 416 RANGE NOT SATISFIABLE\r\n"),
+            "{}",
+            response
+        );
+        assert!(
+            response.contains("\r\nContent-Range: bytes */11\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+    }
+
+    fn connection_response(server: &DevServer, request: &str, notifications_only: bool) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("xtask-wasm-connection-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let live_reload = live_reload::LiveReload::new(&dir, None, "token".to_string());
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        handle_connection(
+            Stream::Tcp(stream),
+            server,
+            &live_reload,
+            &dir,
+            notifications_only,
+        );
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn notifications_only_connection() {
+        let server = DevServer::default().reload(true);
+
+        let response =
+            connection_response(&server, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", true);
+        assert!(
+            response.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"),
+            "{}",
+            response
+        );
+
+        let response = connection_response(
+            &server,
+            "POST /__xtask/reload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\
+            x-xtask-token: token\r\n\r\n",
+            true,
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 204 NO CONTENT\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn notification_requires_token() {
+        let server = DevServer::default().reload(true);
+
+        let response = connection_response(
+            &server,
+            "POST /__xtask/reload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+            false,
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 403 FORBIDDEN\r\n"),
+            "{}",
+            response
+        );
+
+        let response = connection_response(
+            &server,
+            "POST /__xtask/reload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\
+            x-xtask-token: other\r\n\r\n",
+            false,
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 403 FORBIDDEN\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn live_reload_endpoints_check_host() {
+        let server = DevServer::default().reload(true);
+
+        for path in ["/__xtask/reload", "/__xtask/events", "/__xtask/status"] {
+            let response = connection_response(
+                &server,
+                &format!("GET {} HTTP/1.1\r\nHost: example.com\r\n\r\n", path),
+                false,
+            );
+            assert!(
+                response.starts_with("HTTP/1.1 403 FORBIDDEN\r\n"),
+                "{}",
+                response
+            );
+        }
+    }
+
+    struct Disconnected;
+
+    impl Write for Disconnected {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn event_clients_are_limited() {
+        let dir = std::env::temp_dir().join(format!("xtask-wasm-events-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let live_reload = live_reload::LiveReload::new(&dir, None, String::new());
+        for _ in 0..64 {
+            assert!(!live_reload.event_clients_full());
+            live_reload.add_event_client(Box::new(Vec::new()));
+        }
+        assert!(live_reload.event_clients_full());
+
+        let live_reload = live_reload::LiveReload::new(&dir, None, String::new());
+        for _ in 0..64 {
+            live_reload.add_event_client(Box::new(Disconnected));
+        }
+        assert!(!live_reload.event_clients_full());
+    }
+
+    #[test]
+    fn spa_fallback_uses_index_files() {
+        let dir = std::env::temp_dir().join(format!("xtask-wasm-spa-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.html"), "app").unwrap();
+
+        let server = DevServer::default()
+            .spa(true)
+            .index_files(["index.html", "app.html"]);
+        let mut request = ParsedRequest::new("GET", "/some/route", "HTTP/1.1");
+        request
+            .headers
+            .push(("accept".to_string(), "text/html".to_string()));
+        let mut response = Vec::new();
+        handle_request(&mut response, &request, &server, &dir).unwrap();
+        let response = String::from_utf8(response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\napp"), "{}", response);
+    }
+
+    #[test]
+    fn unresponsive_backend_times_out() {
+        let backend = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let server = DevServer::default()
+            .header_timeout(Duration::from_millis(100))
+            .proxy("/api", format!("http://{}", backend.local_addr().unwrap()));
+
+        let request = ParsedRequest::new("GET", "/api/users", "HTTP/1.1");
+        let started = Instant::now();
+        let mut response = Vec::new();
+        let _ = handle_request(&mut response, &request, &server, &std::env::temp_dir());
+        let response = String::from_utf8(response).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(
+            response.starts_with("HTTP/1.1 500 INTERNAL SERVER ERROR\r\n"),
+            "{}",
+            response
+        );
+    }
+}
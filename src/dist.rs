@@ -1,9 +1,17 @@
 use crate::{
-    anyhow::{ensure, Context, Result},
-    camino, clap, default_build_command, metadata,
+    anyhow::{bail, ensure, Context, Result},
+    camino, cargo_metadata, clap, metadata, CspConfig,
 };
 use lazy_static::lazy_static;
-use std::{fs, path::PathBuf, process};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env, ffi, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
 use wasm_bindgen_cli_support::Bindgen;
 
 /// A helper to generate the distributed package.
@@ -39,7 +47,7 @@ use wasm_bindgen_cli_support::Bindgen;
 /// ```
 ///
 /// In this example, we added a `dist` subcommand to build and package the
-/// `my-project` crate. It will run the [`default_build_command`](crate::default_build_command)
+/// `my-project` crate. It will run `cargo build --target wasm32-unknown-unknown`
 /// at the workspace root, copy the content of the `project/static` directory,
 /// generate JS bindings and output two files: `project.js` and `project.wasm`
 /// into the dist directory.
@@ -93,9 +101,16 @@ pub struct Dist {
     /// Name of the example target to run.
     #[clap(long)]
     pub example: Option<String>,
+    /// Build for the target triple.
+    #[clap(long, default_value = "wasm32-unknown-unknown")]
+    pub target: String,
+    /// Do not display the progress of the dist process.
+    #[cfg(feature = "progress")]
+    #[clap(long)]
+    pub no_progress: bool,
 
     /// Command passed to the build process.
-    #[clap(skip = default_build_command())]
+    #[clap(skip = cargo_build_command())]
     pub build_command: process::Command,
     /// Directory of all generated artifacts.
     #[clap(skip)]
@@ -109,16 +124,82 @@ pub struct Dist {
     /// Set the command's current directory as the workspace root.
     #[clap(skip = true)]
     pub run_in_workspace: bool,
+    /// Produce byte-identical artifacts for two builds of the same sources.
+    #[clap(skip)]
+    pub deterministic: bool,
+    /// Names of the custom sections removed from the emitted Wasm.
+    #[clap(skip)]
+    pub strip_custom_sections: Vec<String>,
+    /// Environment variables set for the build command.
+    #[clap(skip)]
+    pub build_env: Vec<(String, String)>,
+    /// Flags appended to the rustc flags of the build command, one argument
+    /// per item.
+    #[clap(skip)]
+    pub rustflags: Vec<String>,
+    /// Crates of the standard library rebuilt with `-Z build-std`.
+    #[clap(skip)]
+    pub build_std: Vec<String>,
+    /// Build with atomics and shared memory to enable multithreading.
+    #[clap(skip)]
+    pub threads: bool,
+    /// Target features enabled for the build (like `+simd128`).
+    #[clap(skip)]
+    pub target_features: Vec<String>,
+    /// Additional Wasm modules loaded lazily, as `(name, package)`.
+    #[clap(skip)]
+    pub lazy_modules: Vec<(String, String)>,
+    /// Generate an npm package instead of a web app.
+    #[clap(skip)]
+    pub npm_package: Option<PackageJson>,
+    /// Command rendering the HTML of a route for prerendering.
+    #[clap(skip)]
+    pub prerender_command: Option<process::Command>,
+    /// Routes prerendered into static HTML files.
+    #[clap(skip)]
+    pub prerender_routes: Vec<String>,
+    /// Merge the outputs into the existing dist directory.
+    #[clap(skip)]
+    pub append: bool,
+    /// Sub-directory of the dist directory receiving the outputs.
+    #[clap(skip)]
+    pub sub_dir: Option<PathBuf>,
+    /// Paths in the dist directory that are not removed between builds.
+    #[clap(skip)]
+    pub preserve_paths: Vec<PathBuf>,
+    /// Minify the HTML files in release mode.
+    #[clap(skip)]
+    pub minify_html: bool,
+    /// Content-Security-Policy injected in the HTML files.
+    #[clap(skip)]
+    pub csp: Option<CspConfig>,
+    /// Inject the module script tag in `index.html` if missing.
+    #[clap(skip)]
+    pub inject_script: bool,
+    /// Bundle the translation files of the `locales` directory.
+    #[clap(skip)]
+    pub locales: bool,
+    /// Convert the translation bundles to JSON.
+    #[clap(skip)]
+    pub locales_json: bool,
     /// Output style for SASS/SCSS
     #[cfg(feature = "sass")]
     #[clap(skip)]
     pub sass_options: sass_rs::Options,
+    /// Ship the Wasm compressed with Brotli and decompress it in the browser.
+    #[cfg(feature = "brotli")]
+    #[clap(skip)]
+    pub brotli: bool,
 }
 
 impl Dist {
     /// Set the command used by the build process.
     ///
-    /// The default command is the result of the [`default_build_command`].
+    /// The default command is `cargo build`. The target set with
+    /// [`Self::target`] is added to it, unless the command already has a
+    /// `--target` argument, like the result of
+    /// [`default_build_command`](crate::default_build_command): the
+    /// target of the command is used then.
     pub fn build_command(mut self, command: process::Command) -> Self {
         self.build_command = command;
         self
@@ -160,28 +241,375 @@ impl Dist {
         self
     }
 
+    #[cfg(feature = "brotli")]
+    /// Compress the Wasm with Brotli and patch the JS glue to decompress it.
+    ///
+    /// The dist directory will contain `<app_name>_bg.wasm.br` next to the
+    /// Wasm binary and the JS glue will fetch and decompress it using a
+    /// [`DecompressionStream`](https://developer.mozilla.org/en-US/docs/Web/API/DecompressionStream).
+    /// This is useful for static hosts that can't serve `Content-Encoding: br`
+    /// themselves.
+    ///
+    /// The browsers whose `DecompressionStream` doesn't support the `br` format
+    /// load the uncompressed Wasm binary instead.
+    pub fn brotli(mut self, res: bool) -> Self {
+        self.brotli = res;
+        self
+    }
+
     /// Set the example to build.
     pub fn example(mut self, example: impl Into<String>) -> Self {
         self.example = Some(example.into());
         self
     }
 
+    /// Make the dist artifacts reproducible.
+    ///
+    /// This remaps the workspace and home paths embedded in the binary, strips
+    /// the producers section and resets the modification time of every file
+    /// in the dist directory, so two builds of the same commit produce
+    /// byte-identical artifacts.
+    pub fn deterministic(mut self, res: bool) -> Self {
+        self.deterministic = res;
+        self
+    }
+
+    /// Remove the given custom sections (like `producers` or
+    /// `target_features`) from the emitted Wasm.
+    ///
+    /// This shrinks the shipped binary without needing the `wasm-opt` feature.
+    pub fn strip_custom_sections<I, S>(mut self, sections: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.strip_custom_sections
+            .extend(sections.into_iter().map(|x| x.as_ref().to_string()));
+        self
+    }
+
+    /// Set an environment variable for the build command.
+    ///
+    /// Unlike replacing the [`build_command`](Self::build_command), this keeps
+    /// all the arguments added by the dist process.
+    pub fn build_env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.build_env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Append flags to the rustc flags of the build command.
+    ///
+    /// Every item is a single argument, like `-Copt-level=z`, and can contain
+    /// spaces. The flags are passed in `CARGO_ENCODED_RUSTFLAGS`, after the
+    /// ones of the `CARGO_ENCODED_RUSTFLAGS` or the `RUSTFLAGS` of the
+    /// environment (or the ones set by [`build_env`](Self::build_env)).
+    ///
+    /// Like with `RUSTFLAGS`, the `build.rustflags` of the cargo configuration
+    /// are then ignored by cargo: move them to this method if needed.
+    pub fn append_rustflags<I, S>(mut self, flags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.rustflags.extend(flags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Rebuild the given crates of the standard library (like `std` and
+    /// `panic_abort`) with `-Z build-std`.
+    ///
+    /// This requires a nightly toolchain, which is checked before the build
+    /// with the toolchain and the environment of the build command: a
+    /// `cargo +nightly` build command, `RUSTUP_TOOLCHAIN` or
+    /// `RUSTC_BOOTSTRAP=1` set with [`Self::build_env`] are taken into account.
+    pub fn build_std<I, S>(mut self, crates: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.build_std.extend(crates.into_iter().map(Into::into));
+        self
+    }
+
+    /// Build the Wasm with atomics and shared memory to enable multithreading.
+    ///
+    /// This sets the required target features in the rustc flags (see
+    /// [`Self::append_rustflags`]) and rebuilds the standard library with
+    /// `-Z build-std`, which requires a nightly toolchain. `wasm-bindgen` then
+    /// generates the shared memory setup of the JS glue.
+    ///
+    /// `SharedArrayBuffer` is only available in a cross-origin isolated
+    /// context: use [`DevServer::cross_origin_isolation`](crate::DevServer::cross_origin_isolation)
+    /// to serve the dist directory.
+    pub fn threads(mut self, res: bool) -> Self {
+        self.threads = res;
+        self
+    }
+
+    /// Enable target features (like `+simd128`) for the build.
+    ///
+    /// The features are added to the rustc flags of the build command (see
+    /// [`Self::append_rustflags`]) and the resulting module is checked to make
+    /// sure they have been enabled.
+    pub fn target_features<I, S>(mut self, features: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.target_features
+            .extend(features.into_iter().map(|x| x.as_ref().to_string()));
+        self
+    }
+
+    /// Add a Wasm module built from another package and loaded lazily.
+    ///
+    /// The package is built along the main one and gets its own JS glue named
+    /// after `name`. A `modules.js` file exporting a `loadModule(name)`
+    /// function is generated in the dist directory to download and
+    /// initialize the module when it is first needed:
+    ///
+    /// ```js
+    /// import { loadModule } from "./modules.js";
+    ///
+    /// const editor = await loadModule("editor");
+    /// ```
+    pub fn lazy_module(mut self, name: impl Into<String>, package: impl Into<String>) -> Self {
+        self.lazy_modules.push((name.into(), package.into()));
+        self
+    }
+
+    /// Generate a publishable npm package in the dist directory.
+    ///
+    /// The JS glue is generated for bundlers along with TypeScript
+    /// declarations, a `package.json` and a copy of the package's README.
+    pub fn npm_package(mut self, package_json: PackageJson) -> Self {
+        self.npm_package = Some(package_json);
+        self
+    }
+
+    /// Set the target triple, the default is `wasm32-unknown-unknown`.
+    ///
+    /// For WASI targets (like `wasm32-wasip1`), no JS bindings are generated:
+    /// the Wasm binary is copied as `<app_name>.wasm` in the dist directory.
+    ///
+    /// The target of a [`build_command`](Self::build_command) with a
+    /// `--target` argument takes precedence.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    /// Prerender the given routes into static HTML files after the build.
+    ///
+    /// The command is executed once per route with the environment variables
+    /// `XTASK_WASM_PRERENDER_ROUTE` (like `/about`) and `XTASK_WASM_DIST_DIR`
+    /// set. It can be a server-side render binary or a script driving a
+    /// headless browser: its standard output is written to `index.html` in
+    /// the directory of the route (`about/index.html` for `/about`).
+    pub fn prerender<I, S>(mut self, command: process::Command, routes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.prerender_command = Some(command);
+        self.prerender_routes
+            .extend(routes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Merge the outputs into the existing dist directory instead of removing
+    /// it first.
+    ///
+    /// This allows combining the outputs of several packages in the same dist
+    /// directory, usually with [`sub_dir`](Self::sub_dir):
+    ///
+    /// ```rust,no_run
+    /// # use xtask_wasm::{anyhow::Result, Dist};
+    /// # fn main() -> Result<()> {
+    /// Dist::default().app_name("app").run("my-app")?;
+    /// Dist::default()
+    ///     .app_name("admin")
+    ///     .append(true)
+    ///     .sub_dir("admin")
+    ///     .run("my-admin")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn append(mut self, res: bool) -> Self {
+        self.append = res;
+        self
+    }
+
+    /// Write the outputs in a sub-directory of the dist directory.
+    pub fn sub_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sub_dir = Some(path.into());
+        self
+    }
+
+    /// Keep a path of the dist directory (like `CNAME` or `.well-known`) when
+    /// the dist directory is cleaned up before a build.
+    ///
+    /// The path is relative to the dist directory.
+    pub fn preserve_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.preserve_paths.push(path.into());
+        self
+    }
+
+    /// Minify the HTML files of the dist directory in release mode.
+    ///
+    /// Comments are removed and whitespaces are collapsed, except in quoted
+    /// attribute values and in `<pre>`, `<textarea>`, `<script>` and `<style>`
+    /// elements.
+    pub fn minify_html(mut self, res: bool) -> Self {
+        self.minify_html = res;
+        self
+    }
+
+    /// Generate a Content-Security-Policy and inject it as a meta tag in the
+    /// HTML files of the dist directory.
+    ///
+    /// See [`CspConfig`] for the generated policy and its interaction with
+    /// the live reload of the development server.
+    pub fn csp(mut self, config: CspConfig) -> Self {
+        self.csp = Some(config);
+        self
+    }
+
+    /// Inject the script loading the app in `index.html` if it has no
+    /// reference to the generated JS.
+    ///
+    /// `<script type="module">import init from './app.js'; init();</script>`
+    /// is inserted before `</head>`, using the configured app name.
+    pub fn inject_script(mut self, res: bool) -> Self {
+        self.inject_script = res;
+        self
+    }
+
+    /// Bundle the translation files of the `locales` directory of the static
+    /// directory.
+    ///
+    /// Every Fluent (`locales/<locale>/**/*.ftl`) or gettext
+    /// (`locales/<locale>/**/*.po`) file is validated and merged into a single
+    /// bundle per locale (`locales/<locale>.ftl`). A `locales.json` index
+    /// mapping every locale to its bundle is written in the dist directory.
+    pub fn locales(mut self, res: bool) -> Self {
+        self.locales = res;
+        self
+    }
+
+    /// Convert the translation bundles to JSON (`locales/<locale>.json`).
+    ///
+    /// This implies [`Dist::locales`].
+    pub fn locales_json(mut self, res: bool) -> Self {
+        self.locales_json = res;
+        self
+    }
+
+    /// Remove the dist directory and the caches of xtask-wasm.
+    ///
+    /// This removes the dist directory, the downloaded `wasm-opt` binary and
+    /// the build information kept in `target/xtask-wasm`. If
+    /// `remove_target_dir` is set, the target directory of the Wasm target
+    /// (like `target/wasm32-unknown-unknown`) is removed too.
+    ///
+    /// # Usage
+    ///
+    /// ```rust,no_run
+    /// use xtask_wasm::{anyhow::Result, clap};
+    ///
+    /// #[derive(clap::Parser)]
+    /// enum Opt {
+    ///     Dist(xtask_wasm::Dist),
+    ///     Clean {
+    ///         /// Remove the Wasm target directory too.
+    ///         #[clap(long)]
+    ///         all: bool,
+    ///     },
+    /// }
+    ///
+    /// fn main() -> Result<()> {
+    ///     let opt: Opt = clap::Parser::parse();
+    ///
+    ///     match opt {
+    ///         Opt::Dist(dist) => {
+    ///             dist.run("my-project")?;
+    ///         }
+    ///         Opt::Clean { all } => {
+    ///             xtask_wasm::Dist::default().clean(all)?;
+    ///         }
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn clean(self, remove_target_dir: bool) -> Result<()> {
+        let target_directory = &metadata().target_directory;
+
+        let mut paths = vec![target_directory.join("xtask-wasm").into_std_path_buf()];
+
+        match self.dist_dir_path {
+            Some(path) => paths.push(path),
+            None => paths.extend(
+                [false, true].map(|release| default_dist_dir(release).as_std_path().to_path_buf()),
+            ),
+        }
+
+        if target_directory.exists() {
+            for entry in fs::read_dir(target_directory)? {
+                let path = entry?.path();
+                if path
+                    .file_name()
+                    .and_then(|x| x.to_str())
+                    .map(|x| x.starts_with("wasm-opt-"))
+                    .unwrap_or(false)
+                {
+                    paths.push(path);
+                }
+            }
+        }
+
+        if remove_target_dir {
+            paths.push(target_directory.join(&self.target).into_std_path_buf());
+        }
+
+        for path in paths.iter().filter(|x| x.exists()) {
+            log::info!("Removing {}", path.display());
+            if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            }
+            .with_context(|| format!("could not remove `{}`", path.display()))?;
+        }
+
+        Ok(())
+    }
+
     /// Build the given package for Wasm.
     ///
     /// This will generate JS bindings via [`wasm-bindgen`](https://docs.rs/wasm-bindgen/latest/wasm_bindgen/)
     /// and copy files from a given static directory if any to finally return
-    /// the path of the generated artifacts.
+    /// the paths of the generated artifacts.
     ///
     /// Wasm optimizations can be achieved using [`crate::WasmOpt`] if the
     /// feature `wasm-opt` is enabled.
-    pub fn run(self, package_name: &str) -> Result<PathBuf> {
+    ///
+    /// The size of every file in the dist directory is recorded in
+    /// `target/xtask-wasm/last-build.json` and the difference with the previous
+    /// build is logged, along with the time taken by every step.
+    pub fn run(self, package_name: &str) -> Result<DistResult> {
+        let build_start = Instant::now();
         log::trace!("Getting package's metadata");
         let metadata = metadata();
 
-        let dist_dir_path = self
+        let mut dist_dir_path = self
             .dist_dir_path
             .unwrap_or_else(|| default_dist_dir(self.release).as_std_path().to_path_buf());
 
+        if let Some(sub_dir) = &self.sub_dir {
+            dist_dir_path.push(sub_dir);
+        }
+
         log::trace!("Initializing dist process");
         let mut build_command = self.build_command;
 
@@ -241,66 +669,309 @@ impl Dist {
             build_command.arg("--ignore-rust-version");
         }
 
+        let target = match command_target(&build_command) {
+            Some(target) => target,
+            None => {
+                build_command.args(["--target", &self.target]);
+                self.target.clone()
+            }
+        };
+
         build_command.args(["--package", package_name]);
 
+        for (_, package) in &self.lazy_modules {
+            build_command.args(["--package", package]);
+        }
+
         if let Some(example) = &self.example {
             build_command.args(["--example", example]);
         }
 
-        let build_dir = metadata
-            .target_directory
-            .join("wasm32-unknown-unknown")
-            .join(if self.release { "release" } else { "debug" });
-        let input_path = if let Some(example) = &self.example {
-            build_dir
-                .join("examples")
-                .join(example.replace('-', "_"))
-                .with_extension("wasm")
-        } else {
-            build_dir
-                .join(package_name.replace('-', "_"))
-                .with_extension("wasm")
-        };
+        for (key, value) in &self.build_env {
+            build_command.env(key, value);
+        }
+
+        let mut rustflags = self.rustflags.clone();
+
+        if self.deterministic {
+            rustflags.push(format!("--remap-path-prefix={}=.", metadata.workspace_root));
+            if let Some(home) = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+                rustflags.push(format!(
+                    "--remap-path-prefix={}=~",
+                    Path::new(&home).display()
+                ));
+            }
+        }
+
+        let mut build_std = self.build_std.clone();
+
+        if self.threads {
+            rustflags.push("-Ctarget-feature=+atomics,+bulk-memory,+mutable-globals".to_string());
+            for krate in ["panic_abort", "std"] {
+                if !build_std.iter().any(|x| x == krate) {
+                    build_std.push(krate.to_string());
+                }
+            }
+        }
 
-        if input_path.exists() {
-            log::trace!("Removing existing target directory");
-            fs::remove_file(&input_path).context("cannot remove existing target")?;
+        if !build_std.is_empty() {
+            ensure_nightly(&build_command)?;
+            build_command.args(["-Z", &format!("build-std={}", build_std.join(","))]);
         }
 
+        if !self.target_features.is_empty() {
+            rustflags.push(format!(
+                "-Ctarget-feature={}",
+                self.target_features.join(",")
+            ));
+        }
+
+        // NOTE: the flags are passed one by one in `CARGO_ENCODED_RUSTFLAGS`,
+        //       which takes precedence over `RUSTFLAGS`, so they can contain
+        //       spaces (like the paths of `--remap-path-prefix`)
+        if !rustflags.is_empty() {
+            let mut encoded: Vec<String> =
+                match command_env(&build_command, "CARGO_ENCODED_RUSTFLAGS") {
+                    Some(existing) => existing
+                        .to_string_lossy()
+                        .split('\x1f')
+                        .filter(|x| !x.is_empty())
+                        .map(ToString::to_string)
+                        .collect(),
+                    None => command_env(&build_command, "RUSTFLAGS")
+                        .map(|x| {
+                            x.to_string_lossy()
+                                .split_whitespace()
+                                .map(ToString::to_string)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                };
+            encoded.extend(rustflags);
+            build_command.env("CARGO_ENCODED_RUSTFLAGS", encoded.join("\x1f"));
+        }
+
+        build_command
+            .args(["--message-format", "json-render-diagnostics"])
+            .stdout(process::Stdio::piped());
+
+        let app_name = self.app_name.unwrap_or_else(|| "app".to_string());
+
+        // NOTE: (output name, package name, package id, example) of every Wasm
+        //       module to generate; the artifacts are matched by package id as
+        //       the name of the targets can be changed in the manifests
+        let mut modules = vec![(
+            app_name.clone(),
+            package_name.to_string(),
+            package_id(metadata, package_name)?,
+            self.example.clone(),
+        )];
+        for (name, package) in &self.lazy_modules {
+            modules.push((
+                name.clone(),
+                package.clone(),
+                package_id(metadata, package)?,
+                None,
+            ));
+        }
+
+        #[cfg(feature = "progress")]
+        let progress = Progress::new(!self.no_progress && !self.quiet);
+        #[cfg(not(feature = "progress"))]
+        let progress = Progress::new(false);
+
+        let mut timings = Vec::new();
+        let mut step_start = Instant::now();
+
+        progress.phase(1, "cargo build");
         log::trace!("Spawning build process");
+        // NOTE: the errors are shown in the browsers by the development server
+        let capture_errors = crate::live_reload::is_enabled();
+        if capture_errors {
+            crate::live_reload::notify_started();
+            build_command.stderr(process::Stdio::piped());
+        }
+        let mut child = build_command.spawn().context("could not start cargo")?;
+        let stderr = child
+            .stderr
+            .take()
+            .map(|stderr| thread::spawn(|| tee(stderr)));
+
+        let mut input_paths = BTreeMap::new();
+        let reader = io::BufReader::new(child.stdout.take().expect("stdout is piped"));
+        for message in cargo_metadata::Message::parse_stream(reader) {
+            if let cargo_metadata::Message::CompilerArtifact(artifact) =
+                message.context("could not read cargo output")?
+            {
+                let is_kind = |kind: &str| artifact.target.kind.iter().any(|x| x == kind);
+                let out_name = modules.iter().find_map(|(out_name, _, id, example)| {
+                    let matches = artifact.package_id == *id
+                        && match example {
+                            Some(example) => is_kind("example") && artifact.target.name == *example,
+                            None => is_kind("cdylib") || is_kind("bin"),
+                        };
+                    matches.then(|| out_name.clone())
+                });
+                let out_name = match out_name {
+                    Some(out_name) => out_name,
+                    None => continue,
+                };
+
+                if let Some(wasm) = artifact
+                    .filenames
+                    .iter()
+                    .find(|x| x.extension() == Some("wasm"))
+                {
+                    // NOTE: the library is preferred to a binary of the same package
+                    if is_kind("cdylib") || !input_paths.contains_key(&out_name) {
+                        input_paths.insert(out_name, wasm.clone());
+                    }
+                }
+            }
+        }
+
+        let status = child.wait().context("could not wait for cargo")?;
+        let errors = stderr.and_then(|x| x.join().ok());
+        if !status.success() {
+            if let Some(errors) = errors {
+                crate::live_reload::notify_error(&errors, build_start.elapsed());
+            }
+            bail!("cargo command failed");
+        }
+
+        let is_wasi = target.contains("wasi");
         ensure!(
-            build_command
-                .status()
-                .context("could not start cargo")?
-                .success(),
-            "cargo command failed"
+            !is_wasi || self.npm_package.is_none(),
+            "npm packages are not supported for WASI targets"
         );
+        let wasm_file_name = |out_name: &str| {
+            if is_wasi {
+                format!("{}.wasm", out_name)
+            } else {
+                format!("{}_bg.wasm", out_name)
+            }
+        };
 
-        let app_name = self.app_name.unwrap_or_else(|| "app".to_string());
+        lap(&mut timings, &mut step_start, "cargo build");
+
+        progress.phase(2, "wasm-bindgen");
+        let mut outputs = Vec::new();
+        let mut wasi_binaries = Vec::new();
+        for (out_name, package, _, example) in &modules {
+            let input_path = input_paths
+                .remove(out_name)
+                .with_context(|| match example {
+                    Some(example) => format!(
+                        "could not find the Wasm artifact of the example `{}` of `{}`",
+                        example, package
+                    ),
+                    None => format!("could not find the Wasm artifact of `{}`", package),
+                })?;
+            log::trace!("Found Wasm artifact at {}", input_path);
+
+            if !self.target_features.is_empty() {
+                log::trace!("Checking target features");
+                let wasm = fs::read(&input_path)
+                    .with_context(|| format!("could not read `{}`", input_path))?;
+                let enabled = wasm_target_features(&wasm)?;
+
+                for feature in self.target_features.iter().filter(|x| x.starts_with('+')) {
+                    ensure!(
+                        enabled.contains(feature),
+                        "target feature `{}` is not enabled in `{}`",
+                        feature,
+                        input_path
+                    );
+                }
+            }
+
+            if is_wasi {
+                wasi_binaries.push((input_path, wasm_file_name(out_name)));
+                continue;
+            }
+
+            log::trace!("Generating Wasm output for `{}`", out_name);
+            let output = Bindgen::new()
+                .input_path(input_path)
+                .out_name(out_name)
+                .web(self.npm_package.is_none())
+                .expect("web have panic")
+                .bundler(self.npm_package.is_some())
+                .expect("bundler have panic")
+                .typescript(self.npm_package.is_some())
+                .debug(!self.release)
+                .remove_producers_section(self.deterministic)
+                .generate_output()
+                .context("could not generate Wasm bindgen file")?;
+            outputs.push(output);
+        }
 
-        log::trace!("Generating Wasm output");
-        let mut output = Bindgen::new()
-            .input_path(input_path)
-            .out_name(&app_name)
-            .web(true)
-            .expect("web have panic")
-            .debug(!self.release)
-            .generate_output()
-            .context("could not generate Wasm bindgen file")?;
-
-        if dist_dir_path.exists() {
+        if self.append {
+            log::trace!("Merging outputs into the existing dist directory");
+        } else if dist_dir_path.exists() {
             log::trace!("Removing already existing dist directory");
-            fs::remove_dir_all(&dist_dir_path)?;
+            if self.preserve_paths.is_empty() {
+                fs::remove_dir_all(&dist_dir_path)?;
+            } else {
+                clean_dist_dir(&dist_dir_path, &dist_dir_path, &self.preserve_paths)?;
+            }
         }
 
         log::trace!("Writing outputs to dist directory");
-        output.emit(&dist_dir_path)?;
+        for output in &mut outputs {
+            output.emit(&dist_dir_path)?;
+        }
+
+        if !wasi_binaries.is_empty() {
+            fs::create_dir_all(&dist_dir_path)?;
+        }
+
+        for (input_path, file_name) in &wasi_binaries {
+            fs::copy(input_path, dist_dir_path.join(file_name))
+                .with_context(|| format!("could not copy `{}`", input_path))?;
+        }
+
+        if !is_wasi && !self.lazy_modules.is_empty() {
+            log::trace!("Generating module loader");
+            fs::write(
+                dist_dir_path.join("modules.js"),
+                module_loader(self.lazy_modules.iter().map(|(name, _)| name.as_str())),
+            )
+            .context("could not write module loader")?;
+        }
+
+        lap(&mut timings, &mut step_start, "wasm-bindgen");
+
+        progress.phase(3, "post-processing");
+        for (out_name, ..) in &modules {
+            if !self.strip_custom_sections.is_empty() {
+                log::trace!("Stripping custom sections of `{}`", out_name);
+                let wasm_path = dist_dir_path.join(wasm_file_name(out_name));
+                let wasm = fs::read(&wasm_path)
+                    .with_context(|| format!("could not read `{}`", wasm_path.display()))?;
+                fs::write(
+                    &wasm_path,
+                    strip_custom_sections(&wasm, &self.strip_custom_sections)?,
+                )
+                .with_context(|| format!("could not write `{}`", wasm_path.display()))?;
+            }
+
+            #[cfg(feature = "brotli")]
+            if self.brotli && !is_wasi {
+                log::trace!("Compressing Wasm of `{}` with Brotli", out_name);
+                brotli(&dist_dir_path, out_name)?;
+            }
+        }
 
+        lap(&mut timings, &mut step_start, "post-processing");
+
+        progress.phase(4, "assets");
         if let Some(static_dir) = self.static_dir_path {
             #[cfg(feature = "sass")]
             {
                 log::trace!("Generating CSS files from SASS/SCSS");
-                sass(&static_dir, &dist_dir_path, &self.sass_options)?;
+                sass(&static_dir, &dist_dir_path, &self.sass_options, &progress)?;
+                lap(&mut timings, &mut step_start, "sass");
             }
 
             #[cfg(not(feature = "sass"))]
@@ -310,17 +981,199 @@ impl Dist {
                 copy_options.content_only = true;
 
                 log::trace!("Copying static directory into dist directory");
-                fs_extra::dir::copy(static_dir, &dist_dir_path, &copy_options)
-                    .context("cannot copy static directory")?;
+                fs_extra::dir::copy_with_progress(
+                    static_dir,
+                    &dist_dir_path,
+                    &copy_options,
+                    |info| {
+                        progress.message(&format!(
+                            "assets: {} ({}%)",
+                            info.file_name,
+                            info.copied_bytes * 100 / info.total_bytes.max(1)
+                        ));
+                        fs_extra::dir::TransitProcessResult::ContinueOrAbort
+                    },
+                )
+                .context("cannot copy static directory")?;
+                lap(&mut timings, &mut step_start, "copy");
             }
         }
 
+        if self.inject_script && !is_wasi {
+            inject_script(&dist_dir_path, &app_name)?;
+        }
+
+        if self.locales || self.locales_json {
+            log::trace!("Bundling translation files");
+            crate::locales::bundle(&dist_dir_path, self.locales_json)?;
+            lap(&mut timings, &mut step_start, "locales");
+        }
+
+        if let Some(mut command) = self.prerender_command {
+            command.env("XTASK_WASM_DIST_DIR", &dist_dir_path);
+
+            for route in &self.prerender_routes {
+                log::trace!("Prerendering `{}`", route);
+                let relative_path = Path::new(route.trim_matches('/'));
+                ensure!(
+                    relative_path
+                        .components()
+                        .all(|x| matches!(x, std::path::Component::Normal(_))),
+                    "invalid route to prerender `{}`",
+                    route
+                );
+
+                let output = command
+                    .env("XTASK_WASM_PRERENDER_ROUTE", route)
+                    .stderr(process::Stdio::inherit())
+                    .output()
+                    .context("could not start prerender command")?;
+                ensure!(
+                    output.status.success(),
+                    "prerender command failed for `{}`",
+                    route
+                );
+
+                let route_dir = dist_dir_path.join(relative_path);
+                fs::create_dir_all(&route_dir)?;
+                fs::write(route_dir.join("index.html"), output.stdout)
+                    .with_context(|| format!("could not write prerendered `{}`", route))?;
+            }
+
+            lap(&mut timings, &mut step_start, "prerender");
+        }
+
+        if self.minify_html && self.release {
+            log::trace!("Minifying HTML files");
+            for file in dist_files(&dist_dir_path)? {
+                if file.extension().and_then(|x| x.to_str()) == Some("html") {
+                    let html = fs::read_to_string(&file)
+                        .with_context(|| format!("could not read `{}`", file.display()))?;
+                    fs::write(&file, minify_html(&html))
+                        .with_context(|| format!("could not write `{}`", file.display()))?;
+                }
+            }
+        }
+
+        if let Some(csp) = &self.csp {
+            log::trace!("Injecting Content-Security-Policy");
+            let files = dist_files(&dist_dir_path)?;
+            let html_files = files
+                .iter()
+                .filter(|x| x.extension().and_then(|x| x.to_str()) == Some("html"))
+                .map(PathBuf::as_path)
+                .collect::<Vec<_>>();
+            let has_wasm = files.iter().any(|x| {
+                let name = x.to_string_lossy();
+                name.ends_with(".wasm") || name.ends_with(".wasm.br")
+            });
+            crate::csp::inject(csp, &html_files, has_wasm)?;
+        }
+
+        if let Some(npm_package) = &self.npm_package {
+            log::trace!("Generating npm package");
+            npm_package.write(&dist_dir_path, package_name, &app_name)?;
+        }
+
+        if self.deterministic {
+            log::trace!("Resetting modification times");
+            for file in dist_files(&dist_dir_path)? {
+                filetime::set_file_mtime(&file, filetime::FileTime::zero()).with_context(|| {
+                    format!("could not reset modification time of `{}`", file.display())
+                })?;
+            }
+        }
+
+        if let Err(err) = log_size_diff(&dist_dir_path) {
+            log::warn!("could not compare sizes with the previous build: {:#}", err);
+        }
+
+        progress.finish();
+        log::info!(
+            "Timings: {}",
+            timings
+                .iter()
+                .map(|(step, duration)| format!("{} {:.2}s", step, duration.as_secs_f32()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
         log::info!("Successfully built in {}", dist_dir_path.display());
 
-        Ok(dist_dir_path)
+        let wasm_name = wasm_file_name(&app_name);
+        #[cfg(feature = "brotli")]
+        let wasm_name = if self.brotli && !is_wasi {
+            format!("{}.br", wasm_name)
+        } else {
+            wasm_name
+        };
+
+        let files = dist_files(&dist_dir_path)?
+            .into_iter()
+            .map(|path| {
+                let content = fs::read(&path)
+                    .with_context(|| format!("could not read `{}`", path.display()))?;
+                let sha256 = Sha256::digest(&content)
+                    .iter()
+                    .map(|x| format!("{:02x}", x))
+                    .collect();
+
+                Ok(DistFile {
+                    path,
+                    size: content.len() as u64,
+                    sha256,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        crate::live_reload::notify(build_start.elapsed());
+
+        Ok(DistResult {
+            js: (!is_wasi).then(|| dist_dir_path.join(format!("{}.js", app_name))),
+            wasm: dist_dir_path.join(wasm_name),
+            dist_dir: dist_dir_path,
+            timings,
+            files,
+        })
+    }
+}
+
+/// Provides paths of the generated dist artifacts.
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct DistResult {
+    /// Directory containing the generated artifacts.
+    pub dist_dir: PathBuf,
+    /// JS glue of the app, `None` when `wasm-bindgen` is not run (for WASI
+    /// targets).
+    pub js: Option<PathBuf>,
+    /// Wasm binary of the app.
+    pub wasm: PathBuf,
+    /// Time taken by every step of the dist process.
+    pub timings: Vec<(String, Duration)>,
+    /// Every file of the dist directory, sorted by path.
+    pub files: Vec<DistFile>,
+}
+
+impl DistResult {
+    /// Get the information of a file of the dist directory, like
+    /// [`DistResult::js`] or [`DistResult::wasm`].
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<&DistFile> {
+        self.files.iter().find(|x| x.path == path.as_ref())
     }
 }
 
+/// A file of the dist directory.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct DistFile {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Hex encoded SHA-256 digest of the file.
+    pub sha256: String,
+}
+
 impl Default for Dist {
     fn default() -> Dist {
         Dist {
@@ -338,13 +1191,165 @@ impl Default for Dist {
             offline: Default::default(),
             ignore_rust_version: Default::default(),
             example: Default::default(),
-            build_command: default_build_command(),
+            target: "wasm32-unknown-unknown".to_string(),
+            #[cfg(feature = "progress")]
+            no_progress: Default::default(),
+            build_command: cargo_build_command(),
             dist_dir_path: Default::default(),
             static_dir_path: Default::default(),
             app_name: Default::default(),
             run_in_workspace: Default::default(),
+            deterministic: Default::default(),
+            strip_custom_sections: Default::default(),
+            build_env: Default::default(),
+            rustflags: Default::default(),
+            build_std: Default::default(),
+            threads: Default::default(),
+            target_features: Default::default(),
+            lazy_modules: Default::default(),
+            npm_package: Default::default(),
+            prerender_command: Default::default(),
+            prerender_routes: Default::default(),
+            append: Default::default(),
+            sub_dir: Default::default(),
+            preserve_paths: Default::default(),
+            minify_html: Default::default(),
+            csp: Default::default(),
+            inject_script: Default::default(),
+            locales: Default::default(),
+            locales_json: Default::default(),
             #[cfg(feature = "sass")]
             sass_options: Default::default(),
+            #[cfg(feature = "brotli")]
+            brotli: Default::default(),
+        }
+    }
+}
+
+/// Check that the build command uses a nightly toolchain, running `rustc` with
+/// its toolchain override (`cargo +nightly`), environment and directory.
+fn ensure_nightly(build_command: &process::Command) -> Result<()> {
+    let var = |key: &str| command_env(build_command, key);
+
+    if var("RUSTC_BOOTSTRAP").filter(|x| x == "1").is_some() {
+        return Ok(());
+    }
+
+    let mut rustc = match var("RUSTC") {
+        Some(rustc) => process::Command::new(rustc),
+        None => {
+            let mut rustc = process::Command::new("rustc");
+            if let Some(toolchain) = build_command
+                .get_args()
+                .next()
+                .and_then(|x| x.to_str())
+                .filter(|x| x.starts_with('+'))
+            {
+                rustc.arg(toolchain);
+            }
+            rustc
+        }
+    };
+
+    for (key, value) in build_command.get_envs() {
+        match value {
+            Some(value) => rustc.env(key, value),
+            None => rustc.env_remove(key),
+        };
+    }
+
+    if let Some(dir) = build_command.get_current_dir() {
+        rustc.current_dir(dir);
+    }
+
+    let output = rustc
+        .arg("--version")
+        .output()
+        .context("could not start rustc")?;
+    let version = String::from_utf8_lossy(&output.stdout);
+
+    ensure!(
+        version.contains("nightly") || version.contains("-dev"),
+        "`-Z build-std` requires a nightly toolchain, found `{}`",
+        version.trim()
+    );
+
+    Ok(())
+}
+
+/// Forward the output of a command to the standard error and return it.
+fn tee(output: impl io::Read) -> String {
+    let mut captured = String::new();
+    let mut stderr = io::stderr();
+    for line in io::BufReader::new(output).lines().map_while(Result::ok) {
+        let _ = writeln!(stderr, "{}", line);
+        captured.push_str(&line);
+        captured.push('\n');
+    }
+    captured
+}
+
+fn lap(timings: &mut Vec<(String, Duration)>, step_start: &mut Instant, step: &str) {
+    timings.push((step.to_string(), step_start.elapsed()));
+    *step_start = Instant::now();
+}
+
+/// Progress bar of the dist process, displayed if the `progress` feature is
+/// enabled.
+struct Progress {
+    #[cfg(feature = "progress")]
+    bar: Option<indicatif::ProgressBar>,
+}
+
+impl Progress {
+    #[cfg(feature = "progress")]
+    const PHASES: u64 = 4;
+
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    fn new(enabled: bool) -> Self {
+        #[cfg(feature = "progress")]
+        {
+            let bar = enabled.then(|| {
+                let bar = indicatif::ProgressBar::new(Self::PHASES);
+                bar.set_style(
+                    indicatif::ProgressStyle::with_template(
+                        "{spinner} [{pos}/{len}] {wide_msg} {elapsed}",
+                    )
+                    .expect("valid progress template"),
+                );
+                bar.enable_steady_tick(std::time::Duration::from_millis(100));
+                bar
+            });
+
+            Progress { bar }
+        }
+
+        #[cfg(not(feature = "progress"))]
+        Progress {}
+    }
+
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    fn phase(&self, step: u64, name: &str) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            bar.set_position(step - 1);
+            bar.set_message(name.to_string());
+        }
+    }
+
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    fn message(&self, message: &str) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            bar.set_message(message.to_string());
+        }
+    }
+
+    fn finish(&self) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            bar.set_position(Self::PHASES);
+            bar.finish_and_clear();
         }
     }
 }
@@ -354,6 +1359,7 @@ fn sass(
     static_dir: &std::path::Path,
     dist_dir: &std::path::Path,
     options: &sass_rs::Options,
+    progress: &Progress,
 ) -> Result<()> {
     fn is_sass(path: &std::path::Path) -> bool {
         matches!(
@@ -379,6 +1385,7 @@ fn sass(
             .with_context(|| format!("cannot walk into directory `{}`", &static_dir.display()))?;
         let source = entry.path();
         let dest = dist_dir.join(source.strip_prefix(static_dir).unwrap());
+        progress.message(&format!("assets: {}", source.display()));
         let _ = fs::create_dir_all(dest.parent().unwrap());
 
         if !source.is_file() {
@@ -402,6 +1409,564 @@ fn sass(
     Ok(())
 }
 
+/// The `package.json` of an npm package generated by [`Dist::npm_package`].
+///
+/// The `name` and `version` default to the ones of the Rust package if empty.
+///
+/// # Usage
+///
+/// ```rust,no_run
+/// # use xtask_wasm::{anyhow::Result, Dist, PackageJson};
+/// # fn main() -> Result<()> {
+/// Dist::default()
+///     .dist_dir_path("pkg")
+///     .npm_package(PackageJson {
+///         name: "@my-org/my-project".to_string(),
+///         license: Some("MIT".to_string()),
+///         ..Default::default()
+///     })
+///     .run("my-project")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PackageJson {
+    /// Name of the npm package.
+    pub name: String,
+    /// Version of the npm package.
+    pub version: String,
+    /// Description of the npm package.
+    pub description: Option<String>,
+    /// License of the npm package.
+    pub license: Option<String>,
+    /// Repository URL of the npm package.
+    pub repository: Option<String>,
+}
+
+impl PackageJson {
+    fn write(&self, dist_dir: &Path, package_name: &str, app_name: &str) -> Result<()> {
+        let package = crate::package(package_name);
+
+        if let Some(readme) = package.and_then(|x| {
+            let readme = x.readme.as_deref().unwrap_or_else(|| "README.md".into());
+            Some(x.manifest_path.parent()?.join(readme)).filter(|x| x.exists())
+        }) {
+            fs::copy(&readme, dist_dir.join("README.md"))
+                .with_context(|| format!("could not copy `{}`", readme))?;
+        }
+
+        let files = dist_files(dist_dir)?
+            .iter()
+            .map(|x| {
+                x.strip_prefix(dist_dir)
+                    .expect("path is inside the dist directory")
+                    .display()
+                    .to_string()
+                    .replace('\\', "/")
+            })
+            .collect::<Vec<_>>();
+
+        let mut package_json = serde_json::Map::new();
+        package_json.insert(
+            "name".into(),
+            if self.name.is_empty() {
+                package_name.into()
+            } else {
+                self.name.as_str().into()
+            },
+        );
+        package_json.insert(
+            "version".into(),
+            if self.version.is_empty() {
+                package
+                    .map(|x| x.version.to_string())
+                    .unwrap_or_else(|| "0.1.0".to_string())
+                    .into()
+            } else {
+                self.version.as_str().into()
+            },
+        );
+        for (key, value) in [
+            ("description", &self.description),
+            ("license", &self.license),
+            ("repository", &self.repository),
+        ] {
+            if let Some(value) = value {
+                package_json.insert(key.into(), value.as_str().into());
+            }
+        }
+        package_json.insert("files".into(), files.into());
+        package_json.insert("module".into(), format!("{}.js", app_name).into());
+        package_json.insert("types".into(), format!("{}.d.ts", app_name).into());
+        package_json.insert(
+            "sideEffects".into(),
+            vec![format!("./{}.js", app_name), "./snippets/*".to_string()].into(),
+        );
+
+        fs::write(
+            dist_dir.join("package.json"),
+            serde_json::to_string_pretty(&package_json)?,
+        )
+        .context("could not write package.json")?;
+
+        Ok(())
+    }
+}
+
+/// Remove the content of `dir` except the preserved paths, relative to `root`.
+fn clean_dist_dir(root: &Path, dir: &Path, preserve_paths: &[PathBuf]) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let relative_path = path
+            .strip_prefix(root)
+            .expect("path is inside the dist directory");
+
+        if preserve_paths.iter().any(|x| x == relative_path) {
+            log::trace!("Preserving {}", path.display());
+        } else if path.is_dir() && preserve_paths.iter().any(|x| x.starts_with(relative_path)) {
+            clean_dist_dir(root, &path, preserve_paths)?;
+        } else if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn minify_html(html: &str) -> String {
+    const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+    let lowercase = html.to_ascii_lowercase();
+    let mut minified = String::with_capacity(html.len());
+    let mut position = 0;
+    // NOTE: the whitespace of the quoted attribute values is kept
+    let mut in_tag = false;
+    let mut quote = None;
+
+    while let Some(c) = html[position..].chars().next() {
+        let rest = &lowercase[position..];
+
+        if in_tag && (quote.is_some() || c == '"' || c == '\'' || c == '>') {
+            quote = match quote {
+                Some(x) if x == c => None,
+                None if c != '>' => Some(c),
+                _ => quote,
+            };
+            in_tag = c != '>' || quote.is_some();
+            minified.push(c);
+            position += c.len_utf8();
+            continue;
+        }
+
+        if rest.starts_with("<!--") && !rest.starts_with("<!--[if") {
+            position = rest
+                .find("-->")
+                .map(|x| position + x + 3)
+                .unwrap_or(html.len());
+            continue;
+        }
+
+        if let Some(element) = RAW_ELEMENTS.iter().find(|x| {
+            rest.strip_prefix('<')
+                .and_then(|rest| rest.strip_prefix(**x))
+                .and_then(|rest| rest.chars().next())
+                .map(|c| c == '>' || c.is_ascii_whitespace())
+                .unwrap_or(false)
+        }) {
+            let closing_tag = format!("</{}", element);
+            let end = rest
+                .find(&closing_tag)
+                .and_then(|x| rest[x..].find('>').map(|y| position + x + y + 1))
+                .unwrap_or(html.len());
+            minified.push_str(&html[position..end]);
+            position = end;
+            continue;
+        }
+
+        if c == '<' && rest[1..].starts_with(|x: char| x.is_ascii_alphabetic() || x == '/') {
+            in_tag = true;
+        }
+
+        if c.is_whitespace() {
+            let end = html[position..]
+                .find(|x: char| !x.is_whitespace())
+                .map(|x| position + x)
+                .unwrap_or(html.len());
+            if !minified.ends_with(' ') {
+                minified.push(' ');
+            }
+            position = end;
+            continue;
+        }
+
+        minified.push(c);
+        position += c.len_utf8();
+    }
+
+    minified.trim().to_string()
+}
+
+fn inject_script(dist_dir: &Path, app_name: &str) -> Result<()> {
+    let index_path = dist_dir.join("index.html");
+    if !index_path.exists() {
+        log::warn!("no index.html in {}", dist_dir.display());
+        return Ok(());
+    }
+
+    let mut index = fs::read_to_string(&index_path)
+        .with_context(|| format!("could not read `{}`", index_path.display()))?;
+    let js_name = format!("{}.js", app_name);
+    if index.contains(&js_name) {
+        return Ok(());
+    }
+
+    match index.to_ascii_lowercase().find("</head>") {
+        Some(position) => {
+            log::trace!("Injecting module script in index.html");
+            index.insert_str(
+                position,
+                &format!(
+                    "<script type=\"module\">import init from './{}'; init();</script>",
+                    js_name
+                ),
+            );
+            fs::write(&index_path, index)
+                .with_context(|| format!("could not write `{}`", index_path.display()))?;
+        }
+        None => log::warn!("could not inject module script: no </head> in index.html"),
+    }
+
+    Ok(())
+}
+
+fn module_loader<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let names = names
+        .map(|x| format!("{:?}", x))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        r#"const names = [{}];
+const modules = {{}};
+
+export function loadModule(name) {{
+    if (!names.includes(name)) {{
+        return Promise.reject(new Error(`unknown module: ${{name}}`));
+    }}
+    if (!(name in modules)) {{
+        modules[name] = import(`./${{name}}.js`).then(async (module) => {{
+            await module.default();
+            return module;
+        }});
+    }}
+    return modules[name];
+}}
+"#,
+        names
+    )
+}
+
+cfg_brotli! {
+    fn brotli(dist_dir: &Path, app_name: &str) -> Result<()> {
+        use std::io::Write;
+
+        let wasm_name = format!("{}_bg.wasm", app_name);
+        let wasm_path = dist_dir.join(&wasm_name);
+        let compressed_path = dist_dir.join(format!("{}.br", wasm_name));
+        let js_path = dist_dir.join(format!("{}.js", app_name));
+
+        let wasm = fs::read(&wasm_path)
+            .with_context(|| format!("could not read `{}`", wasm_path.display()))?;
+        // NOTE: `into_inner` ignores the errors of the last write, which can't
+        //       fail in memory: the errors of the file are reported below
+        let mut writer = brotli_encoder::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        writer
+            .write_all(&wasm)
+            .and_then(|()| writer.flush())
+            .with_context(|| format!("could not compress `{}`", wasm_path.display()))?;
+        fs::write(&compressed_path, writer.into_inner())
+            .with_context(|| format!("could not write `{}`", compressed_path.display()))?;
+
+        let default_url = format!("new URL('{}', import.meta.url)", wasm_name);
+        let js = fs::read_to_string(&js_path)
+            .with_context(|| format!("could not read `{}`", js_path.display()))?;
+        ensure!(
+            js.contains(&default_url),
+            "could not find the Wasm URL in `{}`",
+            js_path.display()
+        );
+        // NOTE: the uncompressed Wasm is kept for the browsers that can't
+        //       decompress Brotli
+        let js = js.replace(
+            &default_url,
+            &format!(
+                "(() => {{ \
+                    try {{ new DecompressionStream('br'); }} catch {{ return {}; }} \
+                    return fetch(new URL('{}.br', import.meta.url)).then((response) => \
+                        new Response(response.body.pipeThrough(new DecompressionStream('br')), \
+                        {{ headers: {{ 'Content-Type': 'application/wasm' }} }})); \
+                }})()",
+                default_url, wasm_name
+            ),
+        );
+        fs::write(&js_path, js)
+            .with_context(|| format!("could not write `{}`", js_path.display()))?;
+
+        Ok(())
+    }
+}
+
+struct WasmSection<'a> {
+    /// The whole section, including its id and size.
+    raw: &'a [u8],
+    /// Name and content of the section if it is a custom section.
+    custom: Option<(&'a [u8], &'a [u8])>,
+}
+
+fn read_leb128(bytes: &[u8], offset: &mut usize) -> Result<usize> {
+    let mut result = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = *bytes.get(*offset).context("truncated Wasm binary")?;
+        *offset += 1;
+        ensure!(shift < 32, "invalid LEB128 integer in Wasm binary");
+        result |= ((byte & 0x7f) as usize) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+fn read_name<'a>(bytes: &'a [u8], offset: &mut usize) -> Result<&'a [u8]> {
+    let len = read_leb128(bytes, offset)?;
+    let name = offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(*offset..end))
+        .context("truncated name in Wasm binary")?;
+    *offset += len;
+    Ok(name)
+}
+
+fn wasm_sections(wasm: &[u8]) -> Result<Vec<WasmSection<'_>>> {
+    ensure!(
+        wasm.len() >= 8 && wasm.starts_with(b"\0asm"),
+        "invalid Wasm binary"
+    );
+
+    let mut sections = Vec::new();
+    let mut offset = 8;
+
+    while offset < wasm.len() {
+        let start = offset;
+        let id = wasm[offset];
+        offset += 1;
+        let size = read_leb128(wasm, &mut offset)?;
+        let end = offset
+            .checked_add(size)
+            .filter(|x| *x <= wasm.len())
+            .context("truncated Wasm section")?;
+
+        // NOTE: custom sections have the id 0 and start with their name
+        let custom = if id == 0 {
+            let content = &wasm[offset..end];
+            let mut name_offset = 0;
+            let name = read_name(content, &mut name_offset)?;
+            Some((name, &content[name_offset..]))
+        } else {
+            None
+        };
+
+        sections.push(WasmSection {
+            raw: &wasm[start..end],
+            custom,
+        });
+        offset = end;
+    }
+
+    Ok(sections)
+}
+
+fn strip_custom_sections(wasm: &[u8], names: &[String]) -> Result<Vec<u8>> {
+    let mut output = wasm[..8].to_vec();
+
+    for section in wasm_sections(wasm)? {
+        if let Some((name, _)) = section.custom {
+            if names.iter().any(|x| x.as_bytes() == name) {
+                log::debug!(
+                    "Removing custom section `{}`",
+                    String::from_utf8_lossy(name)
+                );
+                continue;
+            }
+        }
+
+        output.extend_from_slice(section.raw);
+    }
+
+    Ok(output)
+}
+
+/// Read the features listed in the `target_features` custom section, prefixed
+/// by `+`, `-` or `=`.
+fn wasm_target_features(wasm: &[u8]) -> Result<Vec<String>> {
+    let mut features = Vec::new();
+
+    for section in wasm_sections(wasm)? {
+        if let Some((b"target_features", content)) = section.custom {
+            let mut offset = 0;
+            let count = read_leb128(content, &mut offset)?;
+
+            for _ in 0..count {
+                let prefix = *content
+                    .get(offset)
+                    .context("truncated target features section")?;
+                offset += 1;
+                let name = read_name(content, &mut offset)?;
+                features.push(format!(
+                    "{}{}",
+                    prefix as char,
+                    String::from_utf8_lossy(name)
+                ));
+            }
+        }
+    }
+
+    Ok(features)
+}
+
+type Sizes = BTreeMap<String, u64>;
+
+fn log_size_diff(dist_dir: &Path) -> Result<()> {
+    let sizes_path = metadata()
+        .target_directory
+        .join("xtask-wasm")
+        .join("last-build.json");
+    let dist_dir_key = dist_dir.display().to_string();
+
+    let mut builds: BTreeMap<String, Sizes> = match fs::read(&sizes_path) {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_default(),
+        Err(_) => Default::default(),
+    };
+
+    let mut sizes = Sizes::new();
+    for file in dist_files(dist_dir)? {
+        let name = file
+            .strip_prefix(dist_dir)
+            .expect("path is inside the dist directory")
+            .display()
+            .to_string();
+        sizes.insert(name, file.metadata()?.len());
+    }
+
+    if let Some(previous) = builds.get(&dist_dir_key) {
+        for (file, size) in &sizes {
+            let previous_size = previous.get(file).copied().unwrap_or_default();
+            if *size != previous_size {
+                log::info!(
+                    "{} (`{}`)",
+                    format_size_diff(*size as i64 - previous_size as i64),
+                    file
+                );
+            }
+        }
+
+        for (file, previous_size) in previous {
+            if !sizes.contains_key(file) {
+                log::info!(
+                    "{} (`{}`)",
+                    format_size_diff(-(*previous_size as i64)),
+                    file
+                );
+            }
+        }
+    }
+
+    builds.insert(dist_dir_key, sizes);
+
+    fs::create_dir_all(sizes_path.parent().unwrap())?;
+    fs::write(&sizes_path, serde_json::to_vec_pretty(&builds)?)
+        .with_context(|| format!("could not write `{}`", sizes_path))?;
+
+    Ok(())
+}
+
+/// List the files of the dist directory recursively, sorted by path.
+pub(crate) fn dist_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            files.extend(dist_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn format_size_diff(diff: i64) -> String {
+    if diff.abs() < 1000 {
+        format!("{:+} B", diff)
+    } else if diff.abs() < 1_000_000 {
+        format!("{:+.1} KB", diff as f64 / 1000.0)
+    } else {
+        format!("{:+.1} MB", diff as f64 / 1_000_000.0)
+    }
+}
+
+/// The build command of [`Dist`], without target.
+fn cargo_build_command() -> process::Command {
+    let mut command = process::Command::new("cargo");
+    command.arg("build");
+    command
+}
+
+/// Target triple passed to a build command with `--target`, if any.
+fn command_target(command: &process::Command) -> Option<String> {
+    let mut args = command.get_args().filter_map(|x| x.to_str());
+    while let Some(arg) = args.next() {
+        if arg == "--target" {
+            return args.next().map(ToString::to_string);
+        }
+        if let Some(target) = arg.strip_prefix("--target=") {
+            return Some(target.to_string());
+        }
+    }
+    None
+}
+
+/// Value of an environment variable for a command: the one set on the command
+/// or else the one of the current process.
+fn command_env(command: &process::Command, key: &str) -> Option<ffi::OsString> {
+    match command.get_envs().find(|(x, _)| *x == key) {
+        Some((_, value)) => value.map(ToOwned::to_owned),
+        None => env::var_os(key),
+    }
+}
+
+/// Id of the package with the given name, preferring the workspace members.
+fn package_id(
+    metadata: &cargo_metadata::Metadata,
+    name: &str,
+) -> Result<cargo_metadata::PackageId> {
+    let mut packages = metadata.packages.iter().filter(|x| x.name == name);
+    let package = packages
+        .clone()
+        .find(|x| metadata.workspace_members.contains(&x.id))
+        .or_else(|| packages.next())
+        .with_context(|| format!("could not find the package `{}`", name))?;
+    Ok(package.id.clone())
+}
+
 /// Get the default dist directory.
 ///
 /// The default for debug build is `target/debug/dist` and `target/release/dist`
@@ -420,3 +1985,150 @@ pub fn default_dist_dir(release: bool) -> &'static camino::Utf8Path {
         &DEFAULT_DEBUG_PATH
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_section(name: &str, content: &[u8]) -> Vec<u8> {
+        let mut section = vec![0, (1 + name.len() + content.len()) as u8, name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(content);
+        section
+    }
+
+    fn wasm(sections: &[&[u8]]) -> Vec<u8> {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        for section in sections {
+            wasm.extend_from_slice(section);
+        }
+        wasm
+    }
+
+    #[test]
+    fn read_leb128_values() {
+        let mut offset = 0;
+        assert_eq!(read_leb128(&[0x05], &mut offset).unwrap(), 5);
+        assert_eq!(offset, 1);
+
+        let mut offset = 1;
+        assert_eq!(
+            read_leb128(&[0xff, 0xe5, 0x8e, 0x26], &mut offset).unwrap(),
+            624485
+        );
+        assert_eq!(offset, 4);
+
+        assert!(read_leb128(&[0x80], &mut 0).is_err());
+        assert!(read_leb128(&[0x80; 6], &mut 0).is_err());
+    }
+
+    #[test]
+    fn strip_custom_sections_by_name() {
+        let types = [1, 1, 0];
+        let producers = custom_section("producers", b"\0");
+        let name = custom_section("name", b"");
+
+        assert_eq!(
+            strip_custom_sections(&wasm(&[&types, &producers, &name]), &["producers".into()])
+                .unwrap(),
+            wasm(&[&types, &name])
+        );
+    }
+
+    #[test]
+    fn wasm_sections_custom() {
+        // NOTE: a type section with no type
+        let types = [1, 1, 0];
+        let producers = custom_section("producers", b"\0");
+        let wasm = wasm(&[&types, &producers]);
+
+        let sections = wasm_sections(&wasm).unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].raw, types);
+        assert!(sections[0].custom.is_none());
+        assert_eq!(sections[1].raw, producers.as_slice());
+        assert_eq!(
+            sections[1].custom,
+            Some((b"producers".as_slice(), b"\0".as_slice()))
+        );
+
+        assert!(wasm_sections(b"\0wasm\x01\0\0").is_err());
+        assert!(wasm_sections(&wasm[..wasm.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn wasm_target_features_prefixes() {
+        let features = custom_section("target_features", b"\x02+\x07simd128-\x0bbulk-memory");
+        assert_eq!(
+            wasm_target_features(&wasm(&[&features])).unwrap(),
+            ["+simd128", "-bulk-memory"]
+        );
+        assert!(wasm_target_features(&wasm(&[])).unwrap().is_empty());
+
+        let truncated = custom_section("target_features", b"\x02+\x07simd128");
+        assert!(wasm_target_features(&wasm(&[&truncated])).is_err());
+    }
+
+    #[test]
+    fn minify_html_collapses_whitespace() {
+        assert_eq!(
+            minify_html("<html>\n  <body>\n    <p>Hello   world</p>\n  </body>\n</html>\n"),
+            "<html> <body> <p>Hello world</p> </body> </html>"
+        );
+        assert_eq!(minify_html("<p>a<!-- comment -->b</p>"), "<p>ab</p>");
+    }
+
+    #[test]
+    fn minify_html_keeps_attribute_values() {
+        assert_eq!(
+            minify_html("<div   title=\"a  b\"\n  data-x='c\n d'>e   f</div>"),
+            "<div title=\"a  b\" data-x='c\n d'>e f</div>"
+        );
+        assert_eq!(
+            minify_html("<p title=\"it's  >\">a  b</p>"),
+            "<p title=\"it's  >\">a b</p>"
+        );
+    }
+
+    #[test]
+    fn minify_html_keeps_raw_elements() {
+        assert_eq!(
+            minify_html("<textarea  rows=\"2\">a\n  b</textarea>  <pre>c\n  d</pre>"),
+            "<textarea  rows=\"2\">a\n  b</textarea> <pre>c\n  d</pre>"
+        );
+        assert_eq!(
+            minify_html("<TEXTAREA>a  b</TEXTAREA>"),
+            "<TEXTAREA>a  b</TEXTAREA>"
+        );
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_keeps_uncompressed_fallback() {
+        let dir = std::env::temp_dir().join(format!("xtask-wasm-brotli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app_bg.wasm"), b"\0asm\x01\0\0\0").unwrap();
+        fs::write(
+            dir.join("app.js"),
+            "module_or_path = new URL('app_bg.wasm', import.meta.url);",
+        )
+        .unwrap();
+
+        brotli(&dir, "app").unwrap();
+
+        assert!(dir.join("app_bg.wasm").is_file());
+        assert!(dir.join("app_bg.wasm.br").is_file());
+        let js = fs::read_to_string(dir.join("app.js")).unwrap();
+        assert!(js.contains("new DecompressionStream('br')"), "{}", js);
+        assert!(
+            js.contains("return new URL('app_bg.wasm', import.meta.url);"),
+            "{}",
+            js
+        );
+        assert!(
+            js.contains("new URL('app_bg.wasm.br', import.meta.url)"),
+            "{}",
+            js
+        );
+    }
+}
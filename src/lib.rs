@@ -8,8 +8,9 @@
 //!
 //! # Minimum Supported Rust Version
 //!
-//! This crate requires **Rust 1.58.1** at a minimum because there is a security
-//! issue on a function we use from std in previous version
+//! This crate requires **Rust 1.61** at a minimum because of its dependencies.
+//! This also avoids a security issue on a function we use from std in versions
+//! prior to 1.58.1
 //! (see [cve-2022-21658](https://groups.google.com/g/rustlang-security-announcements/c/R1fZFDhnJVQ)).
 //!
 //! # Setup
@@ -205,6 +206,40 @@
 //! * `run-example`: a helper to run examples from `examples/` directory using a development
 //!     server.
 //! * `sass`: allow the use of SASS/SCSS in your project.
+//! * `progress`: display the progress of the dist process (can be disabled
+//!     using `--no-progress`).
+//! * `brotli`: allow shipping the Wasm compressed with Brotli using
+//!     [`Dist::brotli`](crate::dist::Dist::brotli).
+//! * `tls`: allow serving the development server over HTTPS using
+//!     [`DevServer::tls_self_signed`](crate::dev_server::DevServer::tls_self_signed)
+//!     or [`DevServer::tls`](crate::dev_server::DevServer::tls).
+//! * `qr`: print a QR code of the first network URL of the development
+//!     server (see [`DevServer::urls`](crate::dev_server::DevServer::urls)) when it is bound to an
+//!     unspecified address like `0.0.0.0`.
+//! * `mdns`: announce the development server on the local network using
+//!     [`DevServer::mdns`](crate::dev_server::DevServer::mdns).
+//! * `async-server`: run the development server on
+//!     [tokio](https://docs.rs/tokio) and [hyper](https://docs.rs/hyper)
+//!     instead of a thread per connection. Along with `tls`, this allows
+//!     serving HTTP/2 using
+//!     [`DevServer::http2`](crate::dev_server::DevServer::http2).
+//!
+//! # Breaking changes
+//!
+//! * A custom [`Dist::build_command`](crate::dist::Dist::build_command) without
+//!     a `--target` argument is now built for
+//!     [`Dist::target`](crate::dist::Dist::target), `wasm32-unknown-unknown` by
+//!     default. Previously it was run as is.
+//! * [`Dist::run`](crate::dist::Dist::run) now returns a
+//!     [`DistResult`](crate::dist::DistResult) instead of the path of the dist
+//!     directory, available in its `dist_dir` field. The
+//!     `xtask-wasm-run-example` crate of the `run-example` feature is bumped to
+//!     0.3.0 accordingly.
+//! * [`DistResult::js`](crate::dist::DistResult::js) is now an
+//!     `Option<PathBuf>`, `None` when `wasm-bindgen` is not run (for WASI
+//!     targets).
+//! * The minimum supported Rust version is now 1.61, required by the
+//!     dependencies.
 //!
 //! # Troubleshooting
 //!
@@ -251,11 +286,21 @@ cfg_not_wasm32! {
         Watch,
     };
 
+    mod access_log;
+    mod csp;
     mod dev_server;
     mod dist;
+    mod live_reload;
+    mod locales;
+    mod metrics;
+    mod mock_api;
+    mod serve_log;
+    mod watcher;
 
+    pub use csp::*;
     pub use dev_server::*;
     pub use dist::*;
+    pub use watcher::*;
 
     cfg_run_example! {
         pub use env_logger;
@@ -271,6 +316,14 @@ cfg_not_wasm32! {
         pub use sass_rs;
     }
 
+    cfg_tls! {
+        mod tls;
+    }
+
+    cfg_async_server! {
+        mod async_server;
+    }
+
     /// Get the default command for the build in the dist process.
     ///
     /// This is `cargo build --target wasm32-unknown-unknown`.
@@ -0,0 +1,583 @@
+use crate::{
+    anyhow::{Context, Result},
+    dev_server::{FileCache, ParsedRequest, Stream},
+    metrics::Metrics,
+    serve_log as log,
+};
+use base64::Engine;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    env, fs,
+    hash::{BuildHasher, Hasher},
+    io::Write,
+    net::{SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Path of the WebSocket endpoint, also used to notify the server.
+pub(crate) const RELOAD_PATH: &str = "/__xtask/reload";
+
+/// Path of the JSON endpoint reporting the status of the server.
+pub(crate) const STATUS_PATH: &str = "/__xtask/status";
+
+/// Path of the server-sent events endpoint streaming the build events.
+pub(crate) const EVENTS_PATH: &str = "/__xtask/events";
+
+/// Header of the notifications with the duration of the build in seconds.
+pub(crate) const DURATION_HEADER: &str = "x-xtask-build-duration";
+
+/// Header of the notifications sent when a build starts.
+pub(crate) const STARTED_HEADER: &str = "x-xtask-build-started";
+
+/// Head of the response of the server-sent events endpoint.
+pub(crate) const EVENTS_RESPONSE_HEAD: &str =
+    "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\n\r\n";
+
+/// Environment variable set on the watched command with the address of the
+/// development server.
+pub(crate) const ADDRESS_ENV: &str = "XTASK_WASM_DEV_SERVER";
+
+/// Environment variable set on the watched command with the token required by
+/// the development server to accept its notifications.
+pub(crate) const TOKEN_ENV: &str = "XTASK_WASM_DEV_SERVER_TOKEN";
+
+/// Header of the notifications with the token of the development server.
+const TOKEN_HEADER: &str = "x-xtask-token";
+
+/// Maximum number of clients of the events endpoint.
+const MAX_EVENT_CLIENTS: usize = 64;
+
+/// Interval at which the served directory is checked for changes when there
+/// is no command to notify the server.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Style of the error overlay, in a `<style>` element whose hash is allowed
+/// by the Content-Security-Policy of the page like the script.
+const CLIENT_STYLE: &str = "#__xtask-error{position:fixed;inset:0;z-index:2147483647;margin:0;\
+    padding:2em;overflow:auto;white-space:pre-wrap;font:14px monospace;\
+    color:#ff6b6b;background:rgba(0,0,0,0.9);}";
+
+const CLIENT_SCRIPT: &str = r#"(() => {
+    const protocol = location.protocol === "https:" ? "wss:" : "ws:";
+    const socket = new WebSocket(`${protocol}//${location.host}/__xtask/reload`);
+    socket.onmessage = (event) => {
+        document.getElementById("__xtask-error")?.remove();
+        if (event.data === "reload") {
+            location.reload();
+        } else if (event.data === "css-update") {
+            for (const link of document.querySelectorAll('link[rel="stylesheet"]')) {
+                const url = new URL(link.href);
+                url.searchParams.set("xtask-reload", Date.now());
+                link.href = url.href;
+            }
+        } else if (event.data.startsWith("error:")) {
+            const overlay = document.createElement("pre");
+            overlay.id = "__xtask-error";
+            overlay.title = "Click to dismiss";
+            overlay.textContent = event.data.slice("error:".length);
+            overlay.onclick = () => overlay.remove();
+            document.body.appendChild(overlay);
+        }
+    };
+})();"#;
+
+/// State of the live reload: the connected browsers, the clients of the
+/// events endpoint, the content of the served directory and the result of the
+/// last build.
+pub(crate) struct LiveReload {
+    dist_dir: PathBuf,
+    token: String,
+    file_cache: Option<Arc<FileCache>>,
+    started: Instant,
+    clients: Mutex<Vec<Box<dyn Write + Send>>>,
+    event_clients: Mutex<Vec<Box<dyn Write + Send>>>,
+    files: Mutex<BTreeMap<PathBuf, Vec<u8>>>,
+    last_build: Mutex<Option<Build>>,
+}
+
+/// Result of a build notified to the server.
+struct Build {
+    error: Option<String>,
+    duration: Option<Duration>,
+    finished: SystemTime,
+}
+
+impl LiveReload {
+    pub(crate) fn new(
+        dist_dir: &Path,
+        file_cache: Option<Arc<FileCache>>,
+        token: String,
+    ) -> LiveReload {
+        LiveReload {
+            dist_dir: dist_dir.to_path_buf(),
+            token,
+            file_cache,
+            started: Instant::now(),
+            clients: Default::default(),
+            event_clients: Default::default(),
+            files: Mutex::new(snapshot(dist_dir)),
+            last_build: Default::default(),
+        }
+    }
+
+    /// Add a browser connected to the WebSocket, receiving the frames.
+    ///
+    /// The errors of the last build are sent right away if it failed.
+    pub(crate) fn add_client(&self, mut client: Box<dyn Write + Send>) {
+        let last_build = self.last_build.lock().expect("build lock is not poisoned");
+        if let Some(error) = last_build.as_ref().and_then(|x| x.error.as_ref()) {
+            let frame = text_frame(&format!("error:{}", error));
+            if client
+                .write_all(&frame)
+                .and_then(|()| client.flush())
+                .is_err()
+            {
+                return;
+            }
+        }
+        drop(last_build);
+
+        self.clients
+            .lock()
+            .expect("clients lock is not poisoned")
+            .push(client);
+    }
+
+    /// Whether the maximum number of clients of the events endpoint is reached,
+    /// once the disconnected ones are forgotten.
+    pub(crate) fn event_clients_full(&self) -> bool {
+        let mut clients = self
+            .event_clients
+            .lock()
+            .expect("event clients lock is not poisoned");
+        if clients.len() < MAX_EVENT_CLIENTS {
+            return false;
+        }
+
+        // NOTE: the disconnected clients are only noticed when writing to them
+        let connected = std::mem::take(&mut *clients);
+        for mut client in connected {
+            if client
+                .write_all(b":\n\n")
+                .and_then(|()| client.flush())
+                .is_ok()
+            {
+                clients.push(client);
+            }
+        }
+        clients.len() >= MAX_EVENT_CLIENTS
+    }
+
+    /// Add a client of the events endpoint, receiving the server-sent events.
+    pub(crate) fn add_event_client(&self, client: Box<dyn Write + Send>) {
+        self.event_clients
+            .lock()
+            .expect("event clients lock is not poisoned")
+            .push(client);
+    }
+
+    /// Whether a notification comes from the watched command, the only one
+    /// knowing the token of the server.
+    pub(crate) fn is_authorized(&self, request: &ParsedRequest) -> bool {
+        !self.token.is_empty() && request.header(TOKEN_HEADER) == Some(self.token.as_str())
+    }
+
+    /// Handle a notification sent by [`notify_started`], [`notify`] or
+    /// [`notify_error`].
+    pub(crate) fn notified(&self, request: &ParsedRequest) {
+        if request.header(STARTED_HEADER).is_some() {
+            self.send_event("build-started", serde_json::json!({}));
+            return;
+        }
+
+        let duration = request
+            .header(DURATION_HEADER)
+            .and_then(|x| x.parse::<f64>().ok())
+            .filter(|x| x.is_finite() && *x >= 0.0)
+            .map(Duration::from_secs_f64);
+
+        let error = if request.body.is_empty() {
+            self.update();
+            None
+        } else {
+            let error = String::from_utf8_lossy(&request.body).into_owned();
+            log::info!("Showing the build errors in connected browsers");
+            self.broadcast(&format!("error:{}", error));
+            Some(error)
+        };
+
+        match &error {
+            Some(error) => self.send_event(
+                "build-failed",
+                serde_json::json!({
+                    "error": error,
+                    "duration": duration.map(|x| x.as_secs_f64()),
+                }),
+            ),
+            None => self.send_event(
+                "build-finished",
+                serde_json::json!({ "duration": duration.map(|x| x.as_secs_f64()) }),
+            ),
+        }
+
+        *self.last_build.lock().expect("build lock is not poisoned") = Some(Build {
+            error,
+            duration,
+            finished: SystemTime::now(),
+        });
+    }
+
+    /// Write the response of the status endpoint: the result of the last
+    /// build, the number of served files, the uptime of the server and the
+    /// summary of the handled requests.
+    pub(crate) fn write_status(&self, stream: &mut dyn Write, metrics: &Metrics) -> Result<()> {
+        let last_build = self.last_build.lock().expect("build lock is not poisoned");
+        let build = last_build.as_ref().map(|build| {
+            serde_json::json!({
+                "success": build.error.is_none(),
+                "error": build.error,
+                "duration": build.duration.map(|x| x.as_secs_f64()),
+                "timestamp": build
+                    .finished
+                    .duration_since(UNIX_EPOCH)
+                    .map(|x| x.as_secs())
+                    .unwrap_or_default(),
+            })
+        });
+        let status = serde_json::json!({
+            "build": build,
+            "files": crate::dist::dist_files(&self.dist_dir).map(|x| x.len()).unwrap_or_default(),
+            "uptime": self.started.elapsed().as_secs(),
+            "requests": metrics.to_json(),
+        })
+        .to_string();
+        drop(last_build);
+
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                    Content-Length: {}\r\nCache-Control: no-store\r\n\r\n{}",
+                    status.len(),
+                    status
+                )
+                .as_bytes(),
+            )
+            .context("cannot write response")
+    }
+
+    /// Send a text message to every connected browser, forgetting the
+    /// disconnected ones.
+    pub(crate) fn broadcast(&self, message: &str) {
+        let frame = text_frame(message);
+        let mut clients = self.clients.lock().expect("clients lock is not poisoned");
+        let connected = std::mem::take(&mut *clients);
+        for mut client in connected {
+            if client
+                .write_all(&frame)
+                .and_then(|()| client.flush())
+                .is_ok()
+            {
+                clients.push(client);
+            }
+        }
+        log::debug!("Sent `{}` to {} browser(s)", message, clients.len());
+    }
+
+    /// Send a server-sent event to the clients of the events endpoint,
+    /// forgetting the disconnected ones.
+    fn send_event(&self, event: &str, data: serde_json::Value) {
+        let message = format!("event: {}\ndata: {}\n\n", event, data);
+        let mut clients = self
+            .event_clients
+            .lock()
+            .expect("event clients lock is not poisoned");
+        let connected = std::mem::take(&mut *clients);
+        for mut client in connected {
+            if client
+                .write_all(message.as_bytes())
+                .and_then(|()| client.flush())
+                .is_ok()
+            {
+                clients.push(client);
+            }
+        }
+        log::debug!("Sent `{}` to {} event client(s)", event, clients.len());
+    }
+
+    /// Update the browsers when the content of the served directory changes,
+    /// once it stopped changing.
+    ///
+    /// Used when the server doesn't run a command, for instance when the
+    /// project is built in another terminal, or when the cached files need to
+    /// be refreshed without reloading the browsers.
+    pub(crate) fn watch_dist_dir(self: Arc<Self>, reload: bool) {
+        thread::spawn(move || {
+            let mut current = fingerprint(&self.dist_dir);
+            let mut changed = false;
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let next = fingerprint(&self.dist_dir);
+                if next != current {
+                    current = next;
+                    changed = true;
+                } else if changed {
+                    changed = false;
+                    if reload {
+                        self.update();
+                    } else if let Some(file_cache) = &self.file_cache {
+                        file_cache.clear();
+                    }
+                }
+            }
+        });
+    }
+
+    /// Tell the browsers to update after a build.
+    ///
+    /// The stylesheets are replaced in place if they are the only files that
+    /// changed, otherwise the page is reloaded.
+    pub(crate) fn update(&self) {
+        if let Some(file_cache) = &self.file_cache {
+            file_cache.clear();
+        }
+
+        let files = snapshot(&self.dist_dir);
+        let mut previous = self.files.lock().expect("files lock is not poisoned");
+
+        let mut changed = files
+            .iter()
+            .filter(|(path, hash)| previous.get(*path) != Some(hash))
+            .map(|(path, _)| path)
+            .peekable();
+        let css_only = files.len() == previous.len()
+            && changed.peek().is_some()
+            && changed.all(|path| path.extension() == Some("css".as_ref()));
+        *previous = files;
+
+        if css_only {
+            log::info!("Updating stylesheets of connected browsers");
+            self.broadcast("css-update");
+        } else {
+            log::info!("Reloading connected browsers");
+            self.broadcast("reload");
+        }
+    }
+}
+
+/// Handle a request to the live reload endpoint.
+///
+/// A `POST` request (sent by [`notify_started`], [`notify`] or
+/// [`notify_error`]) updates the connected browsers, any other request is a
+/// WebSocket handshake.
+pub(crate) fn handle(
+    stream: &mut Stream,
+    request: &ParsedRequest,
+    live_reload: &LiveReload,
+) -> Result<()> {
+    if request.method == "POST" {
+        if !live_reload.is_authorized(request) {
+            log::warn!("rejected a build notification without the token of the server");
+            stream
+                .write_all(b"HTTP/1.1 403 FORBIDDEN\r\nContent-Length: 0\r\n\r\n")
+                .context("cannot write response")?;
+            return Ok(());
+        }
+
+        live_reload.notified(request);
+        stream
+            .write_all(b"HTTP/1.1 204 NO CONTENT\r\n\r\n")
+            .context("cannot write response")?;
+        return Ok(());
+    }
+
+    let key = request
+        .header("sec-websocket-key")
+        .context("missing WebSocket key")?;
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            )
+            .as_bytes(),
+        )
+        .context("cannot write response")?;
+
+    live_reload.add_client(Box::new(stream.try_clone()?));
+
+    Ok(())
+}
+
+/// Start the server-sent events stream of a request to the events endpoint.
+pub(crate) fn handle_events(stream: &mut Stream, live_reload: &LiveReload) -> Result<()> {
+    if live_reload.event_clients_full() {
+        log::warn!("too many clients of the events endpoint, refusing the new one");
+        return stream
+            .write_all(b"HTTP/1.1 503 SERVICE UNAVAILABLE\r\nContent-Length: 0\r\n\r\n")
+            .context("cannot write response");
+    }
+
+    stream
+        .write_all(EVENTS_RESPONSE_HEAD.as_bytes())
+        .and_then(|()| stream.flush())
+        .context("cannot write response")?;
+
+    live_reload.add_event_client(Box::new(stream.try_clone()?));
+
+    Ok(())
+}
+
+/// Compute the `Sec-WebSocket-Accept` header of the handshake response.
+pub(crate) fn accept_key(key: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(Sha1::digest(
+        format!("{}{}", key, WEBSOCKET_GUID).as_bytes(),
+    ))
+}
+
+/// Insert the live reload client in an HTML document, allowing it in the
+/// Content-Security-Policy of the document if any.
+///
+/// The `host` of the request is used to allow the WebSocket endpoint.
+pub(crate) fn inject_client(html: &mut String, host: Option<&str>) {
+    crate::csp::allow_live_reload_client(html, CLIENT_SCRIPT, CLIENT_STYLE, host);
+
+    let client = format!(
+        "<style>{}</style><script>{}</script>",
+        CLIENT_STYLE, CLIENT_SCRIPT
+    );
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(position) => html.insert_str(position, &client),
+        None => html.push_str(&client),
+    }
+}
+
+/// Generate the token of the notifications, from the random keys of the hash
+/// maps of the standard library.
+pub(crate) fn new_token() -> String {
+    (0..2)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect()
+}
+
+/// Tell the development server running the current process as its watched
+/// command that a build started.
+pub(crate) fn notify_started() {
+    post(b"", (STARTED_HEADER, "1".to_string()));
+}
+
+/// Ask the development server running the current process as its watched
+/// command to reload the connected browsers after a build.
+pub(crate) fn notify(duration: Duration) {
+    post(b"", (DURATION_HEADER, duration.as_secs_f64().to_string()));
+}
+
+/// Ask the development server running the current process as its watched
+/// command to show the errors of a failed build in the connected browsers.
+pub(crate) fn notify_error(error: &str, duration: Duration) {
+    post(
+        error.as_bytes(),
+        (DURATION_HEADER, duration.as_secs_f64().to_string()),
+    );
+}
+
+/// Whether the current process is the watched command of a development server
+/// with live reload.
+pub(crate) fn is_enabled() -> bool {
+    env::var_os(ADDRESS_ENV).is_some()
+}
+
+/// Send a request to the live reload endpoint of the development server.
+///
+/// The address is either a socket address or the path of a Unix domain socket
+/// prefixed by `unix:`.
+fn post(body: &[u8], header: (&str, String)) {
+    let address = match env::var(ADDRESS_ENV) {
+        Ok(address) => address,
+        Err(_) => return,
+    };
+    let token = env::var(TOKEN_ENV).unwrap_or_default();
+    let request = |stream: &mut dyn Write, host: &str| {
+        stream.write_all(
+            format!(
+                "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n{}: {}\r\n{}: {}\r\n\r\n",
+                RELOAD_PATH,
+                host,
+                body.len(),
+                TOKEN_HEADER,
+                token,
+                header.0,
+                header.1,
+            )
+            .as_bytes(),
+        )?;
+        stream.write_all(body)
+    };
+
+    let result = match address.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(path) => std::os::unix::net::UnixStream::connect(path)
+            .and_then(|mut stream| request(&mut stream, "localhost")),
+        _ => match address.parse::<SocketAddr>() {
+            Ok(socket_address) => {
+                TcpStream::connect_timeout(&socket_address, Duration::from_secs(1))
+                    .and_then(|mut stream| request(&mut stream, &address))
+            }
+            Err(_) => return,
+        },
+    };
+
+    if let Err(err) = result {
+        log::debug!("could not notify the development server: {}", err);
+    }
+}
+
+/// Build a WebSocket frame containing a text message.
+fn text_frame(message: &str) -> Vec<u8> {
+    let mut frame = vec![0x81];
+    let len = message.len();
+    if len < 126 {
+        frame.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(message.as_bytes());
+    frame
+}
+
+/// Modification times and sizes of the files of the dist directory.
+fn fingerprint(dist_dir: &Path) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    crate::dist::dist_files(dist_dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok()?;
+            Some((path, metadata.modified().ok(), metadata.len()))
+        })
+        .collect()
+}
+
+/// Hashes of the files of the dist directory.
+fn snapshot(dist_dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    crate::dist::dist_files(dist_dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|path| {
+            let hash = Sha256::digest(fs::read(&path).ok()?).to_vec();
+            Some((path, hash))
+        })
+        .collect()
+}
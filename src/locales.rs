@@ -0,0 +1,438 @@
+use crate::anyhow::{bail, ensure, Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Bundle the translation files found in the `locales` directory of the dist
+/// directory.
+///
+/// Every `locales/<locale>/**/*.ftl` (or `.po`) is validated and bundled into
+/// `locales/<locale>.ftl` (or `.po`), or `locales/<locale>.json` if `to_json`
+/// is set. A `locales.json` index mapping the locales to their bundle is
+/// written at the root of the dist directory.
+pub(crate) fn bundle(dist_dir: &Path, to_json: bool) -> Result<()> {
+    let locales_dir = dist_dir.join("locales");
+    if !locales_dir.is_dir() {
+        log::warn!("no `locales` directory in {}", dist_dir.display());
+        return Ok(());
+    }
+
+    let mut index = BTreeMap::new();
+
+    for entry in fs::read_dir(&locales_dir)? {
+        let locale_dir = entry?.path();
+        if !locale_dir.is_dir() {
+            continue;
+        }
+
+        let locale = locale_dir
+            .file_name()
+            .and_then(|x| x.to_str())
+            .context("locale directory name contains non-utf8 characters")?
+            .to_string();
+
+        let mut files = Vec::new();
+        translation_files(&locale_dir, &mut files)?;
+        files.sort();
+
+        let extension = match files.first().and_then(|x| x.extension()) {
+            Some(extension) => extension.to_string_lossy().to_string(),
+            None => continue,
+        };
+        ensure!(
+            files.iter().all(|x| x.extension() == files[0].extension()),
+            "locale `{}` mixes Fluent and gettext files",
+            locale
+        );
+
+        let mut source = String::new();
+        let mut messages = BTreeMap::new();
+        for file in &files {
+            let content = fs::read_to_string(file)
+                .with_context(|| format!("could not read `{}`", file.display()))?;
+            let parsed = if extension == "ftl" {
+                parse_fluent(&content)
+            } else {
+                parse_gettext(&content)
+            }
+            .with_context(|| format!("invalid translation file `{}`", file.display()))?;
+
+            for (key, value) in parsed {
+                if messages.insert(key.clone(), value).is_some() {
+                    bail!("duplicate message `{}` in locale `{}`", key, locale);
+                }
+            }
+
+            source.push_str(&content);
+            if !source.ends_with('\n') {
+                source.push('\n');
+            }
+        }
+
+        let bundle_name = if to_json {
+            format!("{}.json", locale)
+        } else {
+            format!("{}.{}", locale, extension)
+        };
+        let bundle_path = locales_dir.join(&bundle_name);
+
+        if to_json {
+            fs::write(&bundle_path, serde_json::to_string_pretty(&messages)?)
+        } else {
+            fs::write(&bundle_path, source)
+        }
+        .with_context(|| format!("could not write `{}`", bundle_path.display()))?;
+
+        fs::remove_dir_all(&locale_dir)?;
+        log::debug!("Bundled {} messages for `{}`", messages.len(), locale);
+        index.insert(locale, format!("locales/{}", bundle_name));
+    }
+
+    fs::write(
+        dist_dir.join("locales.json"),
+        serde_json::to_string_pretty(&index)?,
+    )
+    .context("could not write locales index")?;
+
+    Ok(())
+}
+
+fn translation_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            translation_files(&path, files)?;
+        } else if matches!(
+            path.extension().and_then(|x| x.to_str()),
+            Some("ftl") | Some("po")
+        ) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parse the messages, terms and attributes of a Fluent resource.
+///
+/// Attributes are named `message.attribute` and multiline patterns are joined
+/// with a line feed.
+fn parse_fluent(content: &str) -> Result<Vec<(String, serde_json::Value)>> {
+    let mut entries: Vec<(String, String)> = Vec::new();
+    let mut current: Option<String> = None;
+
+    for (number, line) in content.lines().enumerate() {
+        let number = number + 1;
+
+        if line.trim().is_empty() {
+            continue;
+        } else if line.starts_with(' ') {
+            let message = current
+                .as_ref()
+                .with_context(|| format!("line {}: indented line outside of a message", number))?;
+            let line = line.trim();
+
+            if let Some(attribute) = line.strip_prefix('.') {
+                let (name, value) = attribute
+                    .split_once('=')
+                    .with_context(|| format!("line {}: invalid attribute", number))?;
+                let name = name.trim();
+                ensure!(
+                    is_identifier(name),
+                    "line {}: invalid attribute name `{}`",
+                    number,
+                    name
+                );
+                entries.push((format!("{}.{}", message, name), value.trim().to_string()));
+            } else {
+                let (_, value) = entries.last_mut().expect("a message has been parsed");
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line);
+            }
+        } else if line.starts_with('#') {
+            current = None;
+        } else {
+            let (name, value) = line
+                .split_once('=')
+                .with_context(|| format!("line {}: expected a message", number))?;
+            let name = name.trim();
+            ensure!(
+                is_identifier(name.strip_prefix('-').unwrap_or(name)),
+                "line {}: invalid message name `{}`",
+                number,
+                name
+            );
+            current = Some(name.to_string());
+            entries.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|(key, value)| (key, value.into()))
+        .collect())
+}
+
+/// Parse the translations of a gettext PO file.
+///
+/// Plural translations are returned as an array. The translations with a
+/// context are keyed by `<msgctxt>\u{4}<msgid>`, like in the gettext catalogs.
+fn parse_gettext(content: &str) -> Result<Vec<(String, serde_json::Value)>> {
+    #[derive(Clone, Copy)]
+    enum Field {
+        Context,
+        Id,
+        IdPlural,
+        Str,
+    }
+
+    #[derive(Default)]
+    struct Entry {
+        context: Option<String>,
+        id: Option<String>,
+        id_plural: Option<String>,
+        strings: Vec<String>,
+    }
+
+    fn unquote(value: &str, number: usize) -> Result<String> {
+        let inner = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|x| x.strip_suffix('"'))
+            .with_context(|| format!("line {}: expected a quoted string", number))?;
+
+        let mut unescaped = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => unescaped.push('\n'),
+                    Some('t') => unescaped.push('\t'),
+                    Some(c @ '"') | Some(c @ '\\') => unescaped.push(c),
+                    _ => bail!("line {}: invalid escape sequence", number),
+                }
+            } else {
+                unescaped.push(c);
+            }
+        }
+
+        Ok(unescaped)
+    }
+
+    fn finish(entries: &mut Vec<(String, serde_json::Value)>, entry: &mut Entry) -> Result<()> {
+        let Entry {
+            context,
+            id,
+            id_plural,
+            mut strings,
+        } = std::mem::take(entry);
+
+        let id = match id {
+            Some(id) => id,
+            None => {
+                ensure!(context.is_none(), "missing `msgid` after `msgctxt`");
+                return Ok(());
+            }
+        };
+        ensure!(!strings.is_empty(), "missing translation for `{}`", id);
+
+        // NOTE: the entry with an empty id is the header of the file
+        if id.is_empty() && context.is_none() {
+            return Ok(());
+        }
+
+        let key = match context {
+            Some(context) => format!("{}\u{4}{}", context, id),
+            None => id,
+        };
+        let value = if id_plural.is_some() {
+            strings.into()
+        } else {
+            strings.remove(0).into()
+        };
+        entries.push((key, value));
+
+        Ok(())
+    }
+
+    let mut entries = Vec::new();
+    let mut entry = Entry::default();
+    // NOTE: the following quoted lines continue the last field
+    let mut continued: Option<Field> = None;
+
+    for (number, line) in content.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continued = None;
+            continue;
+        }
+
+        if line.starts_with('"') {
+            let value = unquote(line, number)?;
+            let string = match continued {
+                Some(Field::Context) => entry.context.as_mut(),
+                Some(Field::Id) => entry.id.as_mut(),
+                Some(Field::IdPlural) => entry.id_plural.as_mut(),
+                Some(Field::Str) => entry.strings.last_mut(),
+                None => None,
+            };
+            string
+                .with_context(|| format!("line {}: unexpected string", number))?
+                .push_str(&value);
+            continue;
+        }
+
+        let (keyword, value) = line
+            .split_once(char::is_whitespace)
+            .with_context(|| format!("line {}: expected a keyword", number))?;
+        let value = unquote(value, number)?;
+
+        match keyword {
+            "msgctxt" => {
+                finish(&mut entries, &mut entry)?;
+                entry.context = Some(value);
+                continued = Some(Field::Context);
+            }
+            "msgid" => {
+                if entry.id.is_some() {
+                    finish(&mut entries, &mut entry)
+                        .with_context(|| format!("line {}: invalid entry", number))?;
+                }
+                entry.id = Some(value);
+                continued = Some(Field::Id);
+            }
+            "msgid_plural" => {
+                ensure!(
+                    entry.id.is_some(),
+                    "line {}: `msgid_plural` without `msgid`",
+                    number
+                );
+                entry.id_plural = Some(value);
+                continued = Some(Field::IdPlural);
+            }
+            _ if keyword == "msgstr" || keyword.starts_with("msgstr[") => {
+                ensure!(
+                    entry.id.is_some(),
+                    "line {}: `{}` without `msgid`",
+                    number,
+                    keyword
+                );
+                entry.strings.push(value);
+                continued = Some(Field::Str);
+            }
+            _ => bail!("line {}: unknown keyword `{}`", number, keyword),
+        }
+    }
+
+    finish(&mut entries, &mut entry)?;
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fluent_messages_and_attributes() {
+        let entries = parse_fluent(
+            "# comment\nhello = Hello\n    world\n    .title = Greeting\n-brand = Foo\n",
+        )
+        .unwrap();
+        assert_eq!(
+            entries,
+            [
+                ("hello".to_string(), "Hello\nworld".into()),
+                ("hello.title".to_string(), "Greeting".into()),
+                ("-brand".to_string(), "Foo".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_gettext_skips_the_header() {
+        let entries = parse_gettext(concat!(
+            "msgid \"\"\n",
+            "msgstr \"\"\n",
+            "\"Language: fr\\n\"\n",
+            "\n",
+            "# comment\n",
+            "msgid \"Hello\"\n",
+            "msgstr \"Bonjour\"\n",
+        ))
+        .unwrap();
+        assert_eq!(entries, [("Hello".to_string(), "Bonjour".into())]);
+    }
+
+    #[test]
+    fn parse_gettext_continuation_lines() {
+        let entries = parse_gettext(concat!(
+            "msgctxt \"\"\n",
+            "\"menu\"\n",
+            "msgid \"\"\n",
+            "\"One \"\n",
+            "\"file\"\n",
+            "msgid_plural \"\"\n",
+            "\"{n} \"\n",
+            "\"files\"\n",
+            "msgstr[0] \"\"\n",
+            "\"Un fichier\"\n",
+            "msgstr[1] \"{n} \"\n",
+            "\"fichiers\"\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            entries,
+            [(
+                "menu\u{4}One file".to_string(),
+                serde_json::json!(["Un fichier", "{n} fichiers"])
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_gettext_contexts() {
+        let entries = parse_gettext(concat!(
+            "msgctxt \"verb\"\n",
+            "msgid \"Open\"\n",
+            "msgstr \"Ouvrir\"\n",
+            "\n",
+            "msgctxt \"adjective\"\n",
+            "msgid \"Open\"\n",
+            "msgstr \"Ouvert\"\n",
+            "msgid \"Open\"\n",
+            "msgstr \"Ouvrir\"\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            entries,
+            [
+                ("verb\u{4}Open".to_string(), "Ouvrir".into()),
+                ("adjective\u{4}Open".to_string(), "Ouvert".into()),
+                ("Open".to_string(), "Ouvrir".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_gettext_errors() {
+        assert!(parse_gettext("msgid \"a\"\n").is_err());
+        assert!(parse_gettext("msgctxt \"a\"\nmsgstr \"b\"\n").is_err());
+        assert!(parse_gettext("msgid \"a\"\nmsgstr \"\\x\"\n").is_err());
+        assert!(parse_gettext("\"a\"\n").is_err());
+        assert!(parse_gettext("msgfoo \"a\"\n").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::serve_log as log;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// Number of paths shown in the summaries, the slowest first.
+const SUMMARY_PATHS: usize = 10;
+
+/// Durations and sizes of the responses of the development server, by path.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    paths: Mutex<HashMap<String, PathMetrics>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct PathMetrics {
+    requests: u64,
+    bytes: u64,
+    duration: Duration,
+    max_duration: Duration,
+}
+
+impl Metrics {
+    /// Record a handled request.
+    pub(crate) fn record(&self, path: &str, bytes: u64, duration: Duration) {
+        let mut paths = self.paths.lock().expect("metrics lock is not poisoned");
+        let metrics = paths.entry(path.to_string()).or_default();
+        metrics.requests += 1;
+        metrics.bytes += bytes;
+        metrics.duration += duration;
+        metrics.max_duration = metrics.max_duration.max(duration);
+    }
+
+    /// Totals of every path and the paths that took the longest to serve.
+    fn summary(&self) -> (PathMetrics, Vec<(String, PathMetrics)>) {
+        let paths = self.paths.lock().expect("metrics lock is not poisoned");
+        let mut total = PathMetrics::default();
+        for metrics in paths.values() {
+            total.requests += metrics.requests;
+            total.bytes += metrics.bytes;
+            total.duration += metrics.duration;
+            total.max_duration = total.max_duration.max(metrics.max_duration);
+        }
+
+        let mut slowest: Vec<_> = paths
+            .iter()
+            .map(|(path, metrics)| (path.clone(), metrics.clone()))
+            .collect();
+        slowest.sort_by(|a, b| b.1.duration.cmp(&a.1.duration).then(a.0.cmp(&b.0)));
+        slowest.truncate(SUMMARY_PATHS);
+
+        (total, slowest)
+    }
+
+    /// The summary exposed on the status endpoint.
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let (total, slowest) = self.summary();
+        let paths: Vec<_> = slowest
+            .iter()
+            .map(|(path, metrics)| {
+                serde_json::json!({
+                    "path": path,
+                    "count": metrics.requests,
+                    "bytes": metrics.bytes,
+                    "duration": metrics.duration.as_secs_f64(),
+                    "max_duration": metrics.max_duration.as_secs_f64(),
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "count": total.requests,
+            "bytes": total.bytes,
+            "duration": total.duration.as_secs_f64(),
+            "paths": paths,
+        })
+    }
+
+    /// Log the summary when the server stops.
+    pub(crate) fn log_summary(&self) {
+        let (total, slowest) = self.summary();
+        if total.requests == 0 {
+            return;
+        }
+
+        log::info!(
+            "Served {} request(s), {} in {:.1?}",
+            total.requests,
+            format_size(total.bytes),
+            total.duration,
+        );
+        for (path, metrics) in slowest {
+            log::info!(
+                "{:>10} {:>6} x {:>9} {}",
+                format!("{:.1?}", metrics.duration),
+                metrics.requests,
+                format_size(metrics.bytes),
+                path,
+            );
+        }
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes < 1000 {
+        format!("{} B", bytes)
+    } else if bytes < 1_000_000 {
+        format!("{:.1} KB", bytes as f64 / 1000.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{
+    anyhow::{Context, Result},
+    dev_server::{is_relative_path, reason_phrase},
+    serve_log as log,
+};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Find the fixture of a request in the mocks directory.
+///
+/// The request `GET /api/users` is answered by `api/users.json` (or `.yaml`,
+/// `.yml`). The method and the status can be annotated in the file name before
+/// the extension, like `api/users.POST.201.json`; a fixture annotated with the
+/// method of the request is preferred. `index` is used for paths ending with a
+/// `/`.
+pub(crate) fn find(root: &Path, method: &str, path: &str) -> Option<(PathBuf, u16)> {
+    let (dir, name) = match path.trim_start_matches('/').rsplit_once('/') {
+        Some((dir, "")) => (dir, "index"),
+        Some((dir, name)) => (dir, name),
+        None if path.trim_start_matches('/').is_empty() => ("", "index"),
+        None => ("", path.trim_start_matches('/')),
+    };
+    if !is_relative_path(Path::new(dir)) || !is_relative_path(Path::new(name)) {
+        return None;
+    }
+    let dir = root.join(dir);
+
+    fs::read_dir(&dir)
+        .ok()?
+        .filter_map(|entry| {
+            let file_name = entry.ok()?.file_name().into_string().ok()?;
+            let (stem, extension) = file_name.rsplit_once('.')?;
+            if !matches!(extension, "json" | "yaml" | "yml") {
+                return None;
+            }
+
+            let mut stem = stem;
+            let mut status = 200;
+            if let Some((rest, annotation)) = stem.rsplit_once('.') {
+                if let (3, Ok(code)) = (annotation.len(), annotation.parse()) {
+                    stem = rest;
+                    status = code;
+                }
+            }
+            let mut annotated = false;
+            if let Some((rest, annotation)) = stem.rsplit_once('.') {
+                if annotation.chars().all(|x| x.is_ascii_uppercase()) {
+                    if annotation != method {
+                        return None;
+                    }
+                    stem = rest;
+                    annotated = true;
+                }
+            }
+
+            (stem == name).then(|| (annotated, dir.join(&file_name), status))
+        })
+        .max_by_key(|(annotated, path, _)| (*annotated, std::cmp::Reverse(path.clone())))
+        .map(|(_, path, status)| (path, status))
+}
+
+/// Write the content of a fixture as a JSON response.
+pub(crate) fn respond(
+    stream: &mut dyn Write,
+    fixture: &Path,
+    status: u16,
+    extra_headers: &str,
+    head: bool,
+) -> Result<()> {
+    log::debug!("--> {} ({})", fixture.display(), status);
+
+    let content = fs::read_to_string(fixture)
+        .with_context(|| format!("could not read `{}`", fixture.display()))?;
+    let body = if fixture.extension() == Some("json".as_ref()) {
+        content
+    } else {
+        serde_yaml::from_str::<serde_json::Value>(&content)
+            .with_context(|| format!("invalid YAML in `{}`", fixture.display()))?
+            .to_string()
+    };
+    let body = if status == 204 { "" } else { body.as_str() };
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                Cache-Control: no-store\r\n{}\r\n",
+                status,
+                reason_phrase(status),
+                body.len(),
+                extra_headers,
+            )
+            .as_bytes(),
+        )
+        .context("cannot write response")?;
+    if !head {
+        stream
+            .write_all(body.as_bytes())
+            .context("cannot write response")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Logging macros of the development server, used instead of the ones of the
+//! `log` crate to prefix the messages with `[serve]` when the output of the
+//! command is prefixed with `[build]`, see
+//! [`DevServer::prefix_output`](crate::DevServer::prefix_output).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PREFIX: AtomicBool = AtomicBool::new(false);
+static COLORED: AtomicBool = AtomicBool::new(false);
+
+/// Prefix the messages logged by the server from now on.
+pub(crate) fn enable_prefix(colored: bool) {
+    COLORED.store(colored, Ordering::Relaxed);
+    PREFIX.store(true, Ordering::Relaxed);
+}
+
+/// Prefix of the messages, empty unless enabled.
+pub(crate) fn prefix() -> &'static str {
+    match (
+        PREFIX.load(Ordering::Relaxed),
+        COLORED.load(Ordering::Relaxed),
+    ) {
+        (true, true) => "\x1b[35m[serve]\x1b[0m ",
+        (true, false) => "[serve] ",
+        (false, _) => "",
+    }
+}
+
+macro_rules! log_trace {
+    ($($arg:tt)+) => {
+        ::log::trace!("{}{}", $crate::serve_log::prefix(), format_args!($($arg)+))
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        ::log::debug!("{}{}", $crate::serve_log::prefix(), format_args!($($arg)+))
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)+) => {
+        ::log::info!("{}{}", $crate::serve_log::prefix(), format_args!($($arg)+))
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        ::log::warn!("{}{}", $crate::serve_log::prefix(), format_args!($($arg)+))
+    };
+}
+
+macro_rules! log_error {
+    ($($arg:tt)+) => {
+        ::log::error!("{}{}", $crate::serve_log::prefix(), format_args!($($arg)+))
+    };
+}
+
+pub(crate) use {
+    log_debug as debug, log_error as error, log_info as info, log_trace as trace, log_warn as warn,
+};
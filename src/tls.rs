@@ -0,0 +1,118 @@
+use crate::{
+    anyhow::{bail, ensure, Context, Result},
+    dev_server::Stream,
+};
+use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+use rustls_pemfile::Item;
+use std::{
+    fs,
+    io::BufReader,
+    net::{IpAddr, SocketAddr, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// First byte of a TLS handshake record.
+pub(crate) const HANDSHAKE_RECORD: u8 = 0x16;
+
+/// Generate a throwaway certificate for `localhost` and the bound IP address.
+pub(crate) fn self_signed_config(ip: IpAddr) -> Result<Arc<ServerConfig>> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    if !ip.is_unspecified() && !ip.is_loopback() {
+        names.push(ip.to_string());
+    }
+
+    let certificate = rcgen::generate_simple_self_signed(names)
+        .context("could not generate a self-signed certificate")?;
+    let certificate_der = certificate
+        .serialize_der()
+        .context("could not serialize the self-signed certificate")?;
+    let key_der = certificate.serialize_private_key_der();
+
+    server_config(vec![Certificate(certificate_der)], PrivateKey(key_der))
+}
+
+/// Load a PEM encoded certificate chain and private key.
+pub(crate) fn config_from_files(certificate: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+    let open = |path: &Path| {
+        fs::File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("could not open `{}`", path.display()))
+    };
+
+    let chain: Vec<_> = rustls_pemfile::certs(&mut open(certificate)?)
+        .with_context(|| format!("could not read `{}`", certificate.display()))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    ensure!(
+        !chain.is_empty(),
+        "no certificate found in `{}`",
+        certificate.display()
+    );
+
+    let mut reader = open(key)?;
+    let key_der = loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .with_context(|| format!("could not read `{}`", key.display()))?
+        {
+            Some(Item::RSAKey(der)) | Some(Item::PKCS8Key(der)) | Some(Item::ECKey(der)) => {
+                break der
+            }
+            Some(_) => continue,
+            None => bail!("no private key found in `{}`", key.display()),
+        }
+    };
+
+    server_config(chain, PrivateKey(key_der))
+}
+
+fn server_config(chain: Vec<Certificate>, key: PrivateKey) -> Result<Arc<ServerConfig>> {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .context("invalid certificate")?;
+
+    Ok(Arc::new(config))
+}
+
+/// Start a TLS session on an incoming connection.
+///
+/// Connections that don't start with a TLS handshake are kept in plain text,
+/// this is how the build notifies the server when live reload is enabled. They
+/// are refused unless they come from the machine of the server.
+///
+/// The first byte must be received before `timeout` so idle connections
+/// don't keep the worker busy.
+pub(crate) fn accept(
+    stream: TcpStream,
+    config: &Arc<ServerConfig>,
+    timeout: Option<Duration>,
+) -> Result<Stream> {
+    stream.set_read_timeout(timeout)?;
+    let mut first_byte = [0];
+    stream.peek(&mut first_byte)?;
+    if first_byte[0] != HANDSHAKE_RECORD {
+        let peer = stream.peer_addr()?;
+        ensure!(
+            is_local(peer, stream.local_addr()?),
+            "refused a plain text connection from {} on the HTTPS port",
+            peer
+        );
+        return Ok(Stream::Tcp(stream));
+    }
+
+    let connection =
+        ServerConnection::new(config.clone()).context("could not start TLS session")?;
+
+    Ok(Stream::Tls(Arc::new(Mutex::new(StreamOwned::new(
+        connection, stream,
+    )))))
+}
+
+/// Whether a connection comes from the machine of the server.
+pub(crate) fn is_local(peer: SocketAddr, local: SocketAddr) -> bool {
+    peer.ip().is_loopback() || peer.ip() == local.ip()
+}
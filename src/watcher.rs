@@ -0,0 +1,1910 @@
+use crate::{
+    anyhow::{Context, Result},
+    dev_server::wildcard_match,
+    serve_log as log, Watch,
+};
+use notify::{
+    event::{EventKind, ModifyKind},
+    RecursiveMode, Watcher as _,
+};
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Interval at which the command is checked while waiting for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest delay between the runs of commands that keep failing.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Environment variable giving the changed paths to the commands, separated
+/// like the `PATH`.
+pub(crate) const CHANGED_ENV: &str = "XTASK_WATCH_CHANGED";
+
+/// Files listing the ignored paths of their directory, the last ones taking
+/// precedence.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Kinds of the changes of the watched files, see
+/// [`DevServer::watch_event_kinds`](crate::DevServer::watch_event_kinds).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEventKind {
+    /// A file or a directory was created.
+    Create,
+    /// The content of a file changed.
+    Modify,
+    /// A file or a directory was renamed, or moved.
+    Rename,
+    /// A file or a directory was removed.
+    Remove,
+    /// Only the metadata of a file changed, like its permissions or its
+    /// modification time.
+    Metadata,
+    /// A file was opened or read.
+    Access,
+}
+
+impl WatchEventKind {
+    /// Kind of an event of the file system, `None` for the events that are
+    /// not about a change, like the rescans of the watcher.
+    fn of(kind: &EventKind) -> Option<WatchEventKind> {
+        match kind {
+            EventKind::Access(_) => Some(WatchEventKind::Access),
+            EventKind::Create(_) => Some(WatchEventKind::Create),
+            EventKind::Modify(ModifyKind::Metadata(_)) => Some(WatchEventKind::Metadata),
+            EventKind::Modify(ModifyKind::Name(_)) => Some(WatchEventKind::Rename),
+            // NOTE: some platforms don't tell what changed
+            EventKind::Modify(_) | EventKind::Any => Some(WatchEventKind::Modify),
+            EventKind::Remove(_) => Some(WatchEventKind::Remove),
+            EventKind::Other => None,
+        }
+    }
+}
+
+/// Kinds of changes watched by default: the changes of the content of the
+/// files, without the changes of their metadata.
+pub(crate) fn default_event_kinds() -> Vec<WatchEventKind> {
+    vec![
+        WatchEventKind::Create,
+        WatchEventKind::Modify,
+        WatchEventKind::Rename,
+        WatchEventKind::Remove,
+    ]
+}
+
+/// Context of a run of the function given to
+/// [`DevServer::run_with`](crate::DevServer::run_with), also received from
+/// [`DevServer::change_events`](crate::DevServer::change_events).
+#[non_exhaustive]
+#[derive(Debug, Clone, Default)]
+pub struct WatchContext {
+    /// Paths whose changes triggered the run, empty for the first run.
+    pub changed_paths: Vec<PathBuf>,
+    /// Number of the run, `0` for the first one.
+    pub iteration: usize,
+}
+
+/// Result of a run, given to
+/// [`DevServer::on_result`](crate::DevServer::on_result).
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct WatchResult {
+    /// Whether every command succeeded.
+    pub success: bool,
+    /// Time taken by the run.
+    pub duration: Duration,
+    /// Number of the run, `0` for the first one.
+    pub iteration: usize,
+}
+
+/// Handle to run the command again from the xtask, whatever the changes,
+/// obtained with [`DevServer::trigger`](crate::DevServer::trigger).
+///
+/// The handle can be cloned and sent to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct WatchTrigger(Arc<Mutex<Option<mpsc::Sender<Message>>>>);
+
+impl WatchTrigger {
+    /// Run the command again, interrupting the current run.
+    ///
+    /// Return `false` if the watch is not running, either not started yet or
+    /// stopped.
+    pub fn trigger(&self) -> bool {
+        match &*self.0.lock().unwrap() {
+            Some(sender) => sender.send(Message::Run).is_ok(),
+            None => false,
+        }
+    }
+}
+
+type WatchFn = dyn Fn(&WatchContext) -> Result<()> + Send + Sync;
+
+/// Function executed on each change, see
+/// [`DevServer::run_with`](crate::DevServer::run_with).
+#[derive(Clone)]
+pub(crate) struct RunWith(pub(crate) Arc<WatchFn>);
+
+impl fmt::Debug for RunWith {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RunWith")
+    }
+}
+
+type ResultFn = dyn Fn(&WatchResult) + Send + Sync;
+
+/// Function called after each run, see
+/// [`DevServer::on_result`](crate::DevServer::on_result).
+#[derive(Clone)]
+pub(crate) struct OnResult(pub(crate) Arc<ResultFn>);
+
+impl fmt::Debug for OnResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnResult")
+    }
+}
+
+type FilterFn = dyn Fn(&Path) -> bool + Send + Sync;
+
+/// Function telling whether a changed path is watched, see
+/// [`DevServer::watch_filter`](crate::DevServer::watch_filter).
+#[derive(Clone)]
+pub(crate) struct WatchFilter(pub(crate) Arc<FilterFn>);
+
+impl fmt::Debug for WatchFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WatchFilter")
+    }
+}
+
+/// What is executed on each change, one step after the other.
+#[derive(Debug)]
+pub(crate) enum Step {
+    Command(process::Command),
+    Function(RunWith),
+}
+
+impl Step {
+    /// Execute the step, waiting for it to finish, returning whether it
+    /// succeeded.
+    pub(crate) fn execute(&mut self, context: &WatchContext) -> Result<bool> {
+        match self {
+            Step::Command(command) => Ok(command
+                .status()
+                .context("could not start the command")?
+                .success()),
+            Step::Function(function) => match (function.0)(context) {
+                Ok(()) => Ok(true),
+                Err(err) => {
+                    log::error!("{:#}", err);
+                    Ok(false)
+                }
+            },
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Step::Command(command) => format!("{:?}", command.get_program()),
+            Step::Function(_) => "the function".to_string(),
+        }
+    }
+}
+
+/// Watch loop of the [`DevServer`](crate::DevServer), running the command
+/// again when the watched files change.
+#[derive(Debug)]
+pub(crate) struct Watcher {
+    /// Workspace root, the glob patterns are relative to it.
+    root: PathBuf,
+    watch_paths: Vec<PathBuf>,
+    exclude_paths: Vec<PathBuf>,
+    pub(crate) watch_globs: Vec<String>,
+    pub(crate) exclude_globs: Vec<String>,
+    pub(crate) use_gitignore: bool,
+    pub(crate) watch_hidden: bool,
+    /// Extensions of the watched files, every file is watched without them.
+    pub(crate) extensions: Vec<String>,
+    pub(crate) filters: Vec<WatchFilter>,
+    pub(crate) event_kinds: Vec<WatchEventKind>,
+    pub(crate) clear_terminal: bool,
+    /// Read the keys typed in the terminal.
+    pub(crate) interactive: bool,
+    pub(crate) on_result: Vec<OnResult>,
+    /// Show a desktop notification after each run.
+    pub(crate) desktop_notifications: bool,
+    pub(crate) max_runs: Option<usize>,
+    pub(crate) exit_on_success: bool,
+    pub(crate) restart_on_exit: bool,
+    pub(crate) env_file: Option<PathBuf>,
+    /// Steps executed one after the other, until one of them fails.
+    pub(crate) steps: Vec<Step>,
+    /// Commands executed instead of the steps for the changed paths matching
+    /// their glob pattern.
+    pub(crate) routes: Vec<(String, Step)>,
+    /// Commands executed at the same time as the steps.
+    pub(crate) parallel: Vec<Step>,
+    pub(crate) change_senders: Vec<mpsc::Sender<WatchContext>>,
+    pub(crate) trigger: WatchTrigger,
+    /// Context of the current run.
+    context: WatchContext,
+    /// Number of runs that failed in a row.
+    failures: u32,
+    failed_at: Option<Instant>,
+    /// Number of runs that finished.
+    runs: usize,
+    /// Process groups of the commands started since the last change.
+    #[cfg(unix)]
+    groups: Vec<u32>,
+    /// Variables of the environment file set on the commands.
+    env_keys: Vec<String>,
+    debounce: Duration,
+    /// Rules of the ignore files, by directory.
+    ignore_rules: HashMap<PathBuf, Vec<IgnoreRule>>,
+}
+
+impl Watcher {
+    /// Watch the paths of the watch, the workspace root without them, except
+    /// the excluded paths and the target directory.
+    pub(crate) fn new(watch: Watch, root: &Path, target_dir: &Path) -> Result<Watcher> {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let watch_paths = if watch.watch_paths.is_empty() {
+            vec![root.clone()]
+        } else {
+            watch
+                .watch_paths
+                .iter()
+                .map(|path| {
+                    path.canonicalize()
+                        .with_context(|| format!("could not watch `{}`", path.display()))
+                })
+                .collect::<Result<_>>()?
+        };
+        let exclude_paths = watch
+            .exclude_paths
+            .iter()
+            .cloned()
+            .chain(watch.workspace_exclude_paths.iter().map(|x| root.join(x)))
+            .chain(std::iter::once(target_dir.to_path_buf()))
+            .map(|path| path.canonicalize().unwrap_or(path))
+            .collect();
+
+        Ok(Watcher {
+            root,
+            watch_paths,
+            exclude_paths,
+            watch_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            use_gitignore: false,
+            watch_hidden: false,
+            extensions: Vec::new(),
+            filters: Vec::new(),
+            event_kinds: default_event_kinds(),
+            clear_terminal: false,
+            interactive: false,
+            on_result: Vec::new(),
+            desktop_notifications: false,
+            max_runs: None,
+            exit_on_success: false,
+            restart_on_exit: false,
+            env_file: None,
+            steps: Vec::new(),
+            routes: Vec::new(),
+            parallel: Vec::new(),
+            change_senders: Vec::new(),
+            trigger: WatchTrigger::default(),
+            context: WatchContext::default(),
+            failures: 0,
+            failed_at: None,
+            runs: 0,
+            #[cfg(unix)]
+            groups: Vec::new(),
+            env_keys: Vec::new(),
+            debounce: watch.debounce,
+            ignore_rules: HashMap::new(),
+        })
+    }
+
+    /// Start watching in a thread, running the steps, then running them again
+    /// after each change until the returned handle is stopped. `quit` is called
+    /// when quitting is requested with the keys.
+    pub(crate) fn spawn(mut self, quit: impl FnOnce() + Send + 'static) -> Result<WatchHandle> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher({
+            let sender = sender.clone();
+            move |event| {
+                let _ = sender.send(Message::Event(event));
+            }
+        })
+        .context("could not start watching")?;
+        for path in &self.watch_paths {
+            watcher
+                .watch(path, RecursiveMode::Recursive)
+                .with_context(|| format!("could not watch `{}`", path.display()))?;
+        }
+        log::trace!("Starting to watch");
+
+        for step in self
+            .steps
+            .iter_mut()
+            .chain(self.routes.iter_mut().map(|(_, x)| x))
+            .chain(&mut self.parallel)
+        {
+            if let Step::Command(command) = step {
+                #[cfg(unix)]
+                process_group::set(command);
+                // NOTE: the commands would compete for the keys otherwise
+                if self.interactive {
+                    command.stdin(process::Stdio::null());
+                }
+            }
+        }
+        if self.interactive {
+            read_keys(sender.clone());
+        }
+
+        #[cfg(unix)]
+        sigusr1::install();
+        let trigger = self.trigger.clone();
+        *trigger.0.lock().unwrap() = Some(sender.clone());
+
+        let thread = thread::spawn(move || {
+            // NOTE: the events are no longer sent once the watcher is dropped
+            let _watcher = watcher;
+            self.watch(receiver, quit);
+            *trigger.0.lock().unwrap() = None;
+        });
+
+        Ok(WatchHandle { sender, thread })
+    }
+
+    /// Run the steps, then run them again after each change until stopping or
+    /// quitting.
+    fn watch(mut self, receiver: mpsc::Receiver<Message>, quit: impl FnOnce()) {
+        let mut run = self.start();
+        let mut last_run = Instant::now();
+        let mut changed = Vec::new();
+        let (mut forced, mut paused, mut exited) = (false, false, false);
+        loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(Message::Event(Ok(event))) if !paused => self.record(event, &mut changed),
+                Ok(Message::Event(Ok(_))) => {}
+                Ok(Message::Event(Err(err))) => {
+                    log::warn!("an error occurred when watching: {}", err)
+                }
+                Ok(Message::Run) => forced = true,
+                Ok(Message::Pause) => {
+                    paused = !paused;
+                    changed.clear();
+                    if paused {
+                        log::info!("Paused watching, press p to resume");
+                    } else {
+                        log::info!("Resumed watching");
+                    }
+                }
+                Ok(Message::Quit) => {
+                    log::info!("Quitting");
+                    self.kill(&mut run);
+                    quit();
+                    break;
+                }
+                Ok(Message::Stop) => {
+                    log::trace!("Stopping to watch");
+                    self.kill(&mut run);
+                    break;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            #[cfg(unix)]
+            if sigusr1::received() {
+                log::debug!("Received SIGUSR1");
+                forced = true;
+            }
+
+            if let Some(result) = self.poll(&mut run) {
+                if self.finish(&result) {
+                    quit();
+                    break;
+                }
+                exited = self.restart_on_exit;
+            }
+
+            // NOTE: the changes made right after a run, like the outputs of
+            //       the build, are only picked up once the debounce elapsed
+            let elapsed = last_run.elapsed() >= self.debounce && self.backoff_elapsed();
+            let ready = !changed.is_empty() && elapsed;
+            let restart = exited && elapsed;
+            if forced || ready || restart {
+                self.kill(&mut run);
+                if self.clear_terminal {
+                    clear_terminal();
+                }
+                if !self.steps.is_empty() || !self.routes.is_empty() || !self.parallel.is_empty() {
+                    let message = if forced {
+                        "Running the command again"
+                    } else if ready {
+                        "Changes detected, running the command again"
+                    } else {
+                        "The command exited, running it again"
+                    };
+                    log::info!("{}", message);
+                }
+                log::debug!("Changed paths: {:?}", changed);
+                changed.sort();
+                changed.dedup();
+                self.context = WatchContext {
+                    changed_paths: std::mem::take(&mut changed),
+                    iteration: self.context.iteration + 1,
+                };
+                if !self.context.changed_paths.is_empty() {
+                    let context = &self.context;
+                    self.change_senders
+                        .retain(|sender| sender.send(context.clone()).is_ok());
+                }
+                run = self.start();
+                last_run = Instant::now();
+                forced = false;
+                exited = false;
+            }
+        }
+    }
+
+    /// Add the watched paths of an event to the changed paths.
+    fn record(&mut self, event: notify::Event, changed: &mut Vec<PathBuf>) {
+        let watched = match WatchEventKind::of(&event.kind) {
+            Some(kind) => self.event_kinds.contains(&kind),
+            None => false,
+        };
+        if !watched {
+            log::trace!("Ignored event: {:?}", event);
+            return;
+        }
+
+        for path in event.paths {
+            if let Some(name) = path.file_name().and_then(|x| x.to_str()) {
+                if IGNORE_FILES.contains(&name) {
+                    self.ignore_rules.remove(path.parent().unwrap_or(&path));
+                }
+            }
+            if self.is_watched(&path) {
+                changed.push(path);
+            }
+        }
+    }
+
+    /// Start the steps of the changed paths of the context.
+    fn start(&mut self) -> Option<Run> {
+        if let Some(vars) = self.env_file.as_deref().and_then(read_env_file) {
+            self.set_env(vars);
+        }
+        let (parallel, plan): (Vec<_>, Vec<_>) = self
+            .plan(&self.context.changed_paths)
+            .into_iter()
+            .partition(|x| matches!(x, StepRef::Parallel(_)));
+        let mut run = Run {
+            plan,
+            position: 0,
+            started: Instant::now(),
+            task: None,
+            success: true,
+            parallel: parallel
+                .into_iter()
+                .map(|x| (x, self.spawn_task(x)))
+                .collect(),
+        };
+        self.spawn_step(&mut run);
+        if run.task.is_none() && run.parallel.is_empty() {
+            return None;
+        }
+        Some(run)
+    }
+
+    /// Set the variables of the environment file on the commands, removing
+    /// the ones that are no longer in the file.
+    fn set_env(&mut self, vars: Vec<(String, String)>) {
+        let removed: Vec<_> = self
+            .env_keys
+            .iter()
+            .filter(|key| !vars.iter().any(|(x, _)| x == *key))
+            .collect();
+        for step in self
+            .steps
+            .iter_mut()
+            .chain(self.routes.iter_mut().map(|(_, x)| x))
+            .chain(&mut self.parallel)
+        {
+            if let Step::Command(command) = step {
+                for key in &removed {
+                    command.env_remove(key);
+                }
+                command.envs(vars.iter().map(|(key, value)| (key, value)));
+            }
+        }
+        self.env_keys = vars.into_iter().map(|(key, _)| key).collect();
+    }
+
+    /// Steps executed for the changed paths: the commands of the routes
+    /// matching them, followed by the steps and the parallel commands if a
+    /// path didn't match any route.
+    fn plan(&self, changed_paths: &[PathBuf]) -> Vec<StepRef> {
+        if changed_paths.is_empty() {
+            let steps = (0..self.steps.len()).map(StepRef::Step);
+            let parallel = (0..self.parallel.len()).map(StepRef::Parallel);
+            return steps.chain(parallel).collect();
+        }
+
+        let mut matched = vec![false; self.routes.len()];
+        let mut unmatched = false;
+        for path in changed_paths {
+            let relative = relative_path(path.strip_prefix(&self.root).unwrap_or(path));
+            match self
+                .routes
+                .iter()
+                .position(|(pattern, _)| matches_globs(std::slice::from_ref(pattern), &relative))
+            {
+                Some(index) => matched[index] = true,
+                None => unmatched = true,
+            }
+        }
+
+        let routes = (0..self.routes.len())
+            .filter(|index| matched[*index])
+            .map(StepRef::Route);
+        let steps = (0..self.steps.len())
+            .filter(|_| unmatched)
+            .map(StepRef::Step);
+        let parallel = (0..self.parallel.len())
+            .filter(|_| unmatched)
+            .map(StepRef::Parallel);
+        routes.chain(steps).chain(parallel).collect()
+    }
+
+    fn step_mut(&mut self, step: StepRef) -> &mut Step {
+        match step {
+            StepRef::Step(index) => &mut self.steps[index],
+            StepRef::Route(index) => &mut self.routes[index].1,
+            StepRef::Parallel(index) => &mut self.parallel[index],
+        }
+    }
+
+    /// Start the next step once the current one succeeded, returning
+    /// whether the run succeeded once the steps and the parallel commands are
+    /// finished.
+    fn poll(&mut self, run: &mut Option<Run>) -> Option<WatchResult> {
+        let current = run.as_mut()?;
+
+        if let Some(success) = current.task.as_mut().and_then(Task::finished) {
+            current.task = None;
+            if success {
+                current.position += 1;
+                self.spawn_step(current);
+            } else {
+                current.success = false;
+                if current.position + 1 < current.plan.len() {
+                    log::error!(
+                        "{} failed, the next commands are not executed",
+                        self.step_mut(current.plan[current.position]).name(),
+                    );
+                }
+            }
+        }
+
+        let success = &mut current.success;
+        current
+            .parallel
+            .retain_mut(|(step, task)| match task.finished() {
+                Some(true) => false,
+                Some(false) => {
+                    log::error!("{} failed", self.step_mut(*step).name());
+                    *success = false;
+                    false
+                }
+                None => true,
+            });
+
+        if current.task.is_some() || !current.parallel.is_empty() {
+            return None;
+        }
+
+        let current = run.take().expect("the run is finished");
+        Some(WatchResult {
+            success: current.success,
+            duration: current.started.elapsed(),
+            iteration: self.context.iteration,
+        })
+    }
+
+    /// Record the result of a run, delaying the next one when the runs keep
+    /// failing. Return whether to quit.
+    fn finish(&mut self, result: &WatchResult) -> bool {
+        for on_result in &self.on_result {
+            (on_result.0)(result);
+        }
+        if self.desktop_notifications {
+            notify_desktop(result);
+        }
+
+        self.runs += 1;
+        if result.success && self.exit_on_success {
+            log::info!("The command succeeded, quitting");
+            return true;
+        }
+        if matches!(self.max_runs, Some(max_runs) if self.runs >= max_runs) {
+            log::info!("The command ran {} times, quitting", self.runs);
+            return true;
+        }
+
+        if result.success {
+            self.failures = 0;
+            self.failed_at = None;
+            return false;
+        }
+
+        self.failures += 1;
+        self.failed_at = Some(Instant::now());
+        let backoff = backoff(self.failures);
+        if backoff.is_zero() {
+            log::error!("the command failed, waiting for changes");
+        } else {
+            log::error!(
+                "the command failed {} times in a row, waiting for changes and at least {}s",
+                self.failures,
+                backoff.as_secs(),
+            );
+        }
+        false
+    }
+
+    /// Whether the delay after the last failures elapsed.
+    fn backoff_elapsed(&self) -> bool {
+        match self.failed_at {
+            Some(failed_at) => failed_at.elapsed() >= backoff(self.failures),
+            None => true,
+        }
+    }
+
+    /// Start the step of the plan at the position of the run, if any.
+    fn spawn_step(&mut self, run: &mut Run) {
+        run.task = run
+            .plan
+            .get(run.position)
+            .map(|step| self.spawn_task(*step));
+    }
+
+    /// Start a step, logging the error if it can't be started.
+    fn spawn_task(&mut self, step: StepRef) -> Task {
+        let context = self.context.clone();
+        let task = match self.step_mut(step) {
+            Step::Command(command) => match set_changed_paths(command, &context).spawn() {
+                Ok(child) => Task::Child(child),
+                Err(err) => {
+                    log::error!("could not start {:?}: {}", command.get_program(), err);
+                    Task::Failed
+                }
+            },
+            Step::Function(function) => {
+                let function = function.clone();
+                Task::Function(Some(thread::spawn(move || (function.0)(&context))))
+            }
+        };
+
+        #[cfg(unix)]
+        if let Task::Child(child) = &task {
+            process_group::add(child.id());
+            self.groups.push(child.id());
+        }
+        task
+    }
+
+    /// Stop the run being executed, and the processes left by the commands of
+    /// the previous runs.
+    fn kill(&mut self, run: &mut Option<Run>) {
+        if let Some(run) = run.as_mut() {
+            run.kill();
+        }
+        #[cfg(unix)]
+        for pid in self.groups.drain(..) {
+            process_group::kill(pid);
+        }
+    }
+
+    /// Whether the changes of a path restart the command.
+    fn is_watched(&mut self, path: &Path) -> bool {
+        if self.exclude_paths.iter().any(|x| path.starts_with(x)) {
+            return false;
+        }
+
+        let relative = match self
+            .watch_paths
+            .iter()
+            .find_map(|x| path.strip_prefix(x).ok())
+        {
+            Some(relative) => relative,
+            None => return false,
+        };
+        // NOTE: the directory of git changes on most of its commands
+        let hidden = |name: &std::ffi::OsStr| {
+            name == ".git" || (!self.watch_hidden && name.to_string_lossy().starts_with('.'))
+        };
+        if relative
+            .components()
+            .any(|x| matches!(x, Component::Normal(name) if hidden(name)))
+        {
+            return false;
+        }
+
+        if !self.extensions.is_empty() {
+            let extension = path
+                .extension()
+                .and_then(|x| x.to_str())
+                .unwrap_or_default();
+            if !self
+                .extensions
+                .iter()
+                .any(|x| x.trim_start_matches('.') == extension)
+            {
+                return false;
+            }
+        }
+
+        if self.use_gitignore && self.is_ignored(path) {
+            return false;
+        }
+
+        let relative = relative_path(path.strip_prefix(&self.root).unwrap_or(path));
+        (self.watch_globs.is_empty() || matches_globs(&self.watch_globs, &relative))
+            && !matches_globs(&self.exclude_globs, &relative)
+            && self.filters.iter().all(|filter| (filter.0)(path))
+    }
+
+    /// Whether a path of the workspace, or one of its parent directories, is
+    /// ignored by the ignore files of its parent directories.
+    fn is_ignored(&mut self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative_path(relative),
+            Err(_) => return false,
+        };
+        let segments: Vec<_> = relative.split('/').filter(|x| !x.is_empty()).collect();
+
+        // NOTE: like git, the files of an ignored directory can't be included
+        //       again
+        for end in 1..=segments.len() {
+            let is_dir = end < segments.len() || path.is_dir();
+            let mut ignored = false;
+            for start in 0..end {
+                let dir = segments[..start]
+                    .iter()
+                    .fold(self.root.clone(), |dir, x| dir.join(x));
+                let candidate = segments[start..end].join("/");
+                for rule in self.ignore_rules(dir) {
+                    if rule.matches(&candidate, is_dir) {
+                        ignored = !rule.negated;
+                    }
+                }
+            }
+            if ignored {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Rules of the ignore files of a directory, read on first use.
+    fn ignore_rules(&mut self, dir: PathBuf) -> &[IgnoreRule] {
+        self.ignore_rules.entry(dir).or_insert_with_key(|dir| {
+            IGNORE_FILES
+                .iter()
+                .filter_map(|name| fs::read_to_string(dir.join(name)).ok())
+                .flat_map(|content| parse_ignore_file(&content))
+                .collect()
+        })
+    }
+}
+
+/// A line of an ignore file, using the syntax of `.gitignore`.
+#[derive(Debug, PartialEq, Eq)]
+struct IgnoreRule {
+    pattern: String,
+    /// Paths matching a rule starting with `!` are included again.
+    negated: bool,
+    /// Rules ending with `/` only match directories.
+    directory: bool,
+    /// Rules containing a `/` match paths relative to the directory of the
+    /// ignore file, the others match the file names at any depth.
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            let pattern: Vec<_> = self.pattern.split('/').collect();
+            let path: Vec<_> = path.split('/').collect();
+            segments_match(&pattern, &path)
+        } else {
+            glob_match(&self.pattern, path)
+        }
+    }
+}
+
+fn parse_ignore_file(content: &str) -> Vec<IgnoreRule> {
+    content
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let directory = line.ends_with('/');
+            let line = line.trim_end_matches('/');
+            IgnoreRule {
+                anchored: line.contains('/'),
+                pattern: line.trim_start_matches('/').to_string(),
+                negated,
+                directory,
+            }
+        })
+        .collect()
+}
+
+/// Variables of an environment file, `None` if it can't be read.
+pub(crate) fn read_env_file(path: &Path) -> Option<Vec<(String, String)>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Some(parse_env_file(&content)),
+        Err(err) => {
+            log::warn!(
+                "could not read the environment file `{}`: {}",
+                path.display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Variables of an environment file: `KEY=value` lines, optionally prefixed
+/// by `export`, with `#` comments and quoted values.
+fn parse_env_file(content: &str) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                log::warn!("invalid line in the environment file: `{}`", line);
+                continue;
+            }
+        };
+        let value = if let Some(value) = value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+            value.replace("\\n", "\n").replace("\\\"", "\"")
+        } else if let Some(value) = value.strip_prefix('\'').and_then(|x| x.strip_suffix('\'')) {
+            value.to_string()
+        } else {
+            match value.find(" #") {
+                Some(index) => value[..index].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    vars
+}
+
+/// Show the result of a run in a desktop notification.
+fn notify_desktop(result: &WatchResult) {
+    let message = if result.success {
+        format!("Build succeeded in {:.1}s", result.duration.as_secs_f64())
+    } else {
+        format!("Build failed after {:.1}s", result.duration.as_secs_f64())
+    };
+
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = process::Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification \"{}\" with title \"xtask-wasm\"",
+            message
+        ));
+        command
+    } else if cfg!(unix) {
+        let mut command = process::Command::new("notify-send");
+        command.args(["xtask-wasm", &message]);
+        command
+    } else {
+        log::debug!("desktop notifications are only supported on Unix");
+        return;
+    };
+
+    if let Err(err) = command
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()
+    {
+        log::debug!("could not show a desktop notification: {}", err);
+    }
+}
+
+/// Watch running in a thread, started by [`Watcher::spawn`].
+#[derive(Debug)]
+pub(crate) struct WatchHandle {
+    sender: mpsc::Sender<Message>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl WatchHandle {
+    /// Stop watching, killing the command being executed.
+    ///
+    /// A function being executed can't be interrupted, the watch stops once it
+    /// returned.
+    pub(crate) fn stop(&self) {
+        let _ = self.sender.send(Message::Stop);
+    }
+
+    /// Wait for the watch to stop.
+    pub(crate) fn join(self) {
+        if self.thread.join().is_err() {
+            log::error!("the watch panicked");
+        }
+    }
+}
+
+/// Messages received by the watch loop.
+#[derive(Debug)]
+enum Message {
+    Event(notify::Result<notify::Event>),
+    /// Run the steps again, whatever the changes.
+    Run,
+    /// Pause or resume watching.
+    Pause,
+    /// Stop the server and the watch.
+    Quit,
+    /// Stop the watch only.
+    Stop,
+}
+
+impl Message {
+    /// Message of a line typed in the terminal.
+    fn of_key(line: &str) -> Option<Message> {
+        match line.trim() {
+            "r" => Some(Message::Run),
+            "p" => Some(Message::Pause),
+            "q" => Some(Message::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Run the command again when the process receives `SIGUSR1`, sent by other
+/// tools with `kill -USR1 <pid>`.
+#[cfg(unix)]
+mod sigusr1 {
+    use super::log;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    };
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn handle(_: libc::c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    /// Install the handler of the signal, once for the process.
+    pub(super) fn install() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            // SAFETY: the handler only stores to an atomic, which is
+            //         async-signal-safe
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) != 0 {
+                    log::debug!(
+                        "could not handle SIGUSR1: {}",
+                        std::io::Error::last_os_error()
+                    );
+                }
+            }
+        });
+    }
+
+    /// Whether the signal was received since the last call.
+    pub(super) fn received() -> bool {
+        RECEIVED.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Process groups of the commands, to stop the processes they start too, like
+/// a server started by a script.
+#[cfg(unix)]
+mod process_group {
+    use super::log;
+    use std::{
+        io,
+        os::unix::process::CommandExt,
+        process,
+        sync::{
+            atomic::{AtomicI32, Ordering},
+            Once,
+        },
+    };
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicI32 = AtomicI32::new(0);
+
+    /// Process groups of the running commands, for the signal handler.
+    static GROUPS: [AtomicI32; 64] = [NONE; 64];
+
+    /// Start the command in its own process group.
+    pub(super) fn set(command: &mut process::Command) {
+        // SAFETY: `setpgid` is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Remember the process group of a command, to forward it the signals
+    /// stopping this process.
+    pub(super) fn add(pid: u32) {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(install);
+
+        if !GROUPS.iter().any(|x| {
+            x.compare_exchange(0, pid as i32, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        }) {
+            log::debug!(
+                "too many commands, the signals are not forwarded to {}",
+                pid
+            );
+        }
+    }
+
+    /// Kill all the processes of the process group of a command.
+    pub(super) fn kill(pid: u32) {
+        for group in &GROUPS {
+            let _ = group.compare_exchange(pid as i32, 0, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        // SAFETY: the process group is the one of a command started by the
+        //         watch
+        if unsafe { libc::kill(-(pid as i32), libc::SIGKILL) } != 0 {
+            log::trace!(
+                "could not kill the process group {}: {}",
+                pid,
+                io::Error::last_os_error()
+            );
+        }
+    }
+
+    extern "C" fn forward(signal: libc::c_int) {
+        for group in &GROUPS {
+            let pgid = group.load(Ordering::SeqCst);
+            if pgid != 0 {
+                // SAFETY: `kill` is async-signal-safe
+                unsafe { libc::kill(-pgid, signal) };
+            }
+        }
+        // SAFETY: `signal` and `raise` are async-signal-safe
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    /// Forward the signals stopping this process to the commands, which
+    /// don't receive the ones of the terminal in their own process group.
+    ///
+    /// The signals already handled by the xtask are left untouched.
+    fn install() {
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            // SAFETY: the handler only calls async-signal-safe functions
+            unsafe {
+                let mut previous: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(signal, std::ptr::null(), &mut previous) != 0
+                    || previous.sa_sigaction != libc::SIG_DFL
+                {
+                    continue;
+                }
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// Send the keys typed in the terminal to the watch loop.
+fn read_keys(sender: mpsc::Sender<Message>) {
+    log::info!("Press r to run the command again, p to pause or resume watching and q to quit");
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    log::debug!("could not read the keys: {}", err);
+                    break;
+                }
+            };
+            match Message::of_key(&line) {
+                Some(message) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                None if line.trim().is_empty() => {}
+                None => log::warn!("unknown key `{}`, the keys are r, p and q", line.trim()),
+            }
+        }
+    });
+}
+
+/// Give the changed paths of the run to a command, none for the first run.
+fn set_changed_paths<'a>(
+    command: &'a mut process::Command,
+    context: &WatchContext,
+) -> &'a mut process::Command {
+    if context.changed_paths.is_empty() {
+        return command.env_remove(CHANGED_ENV);
+    }
+
+    match env::join_paths(&context.changed_paths) {
+        Ok(paths) => command.env(CHANGED_ENV, paths),
+        Err(err) => {
+            log::warn!("could not give the changed paths to the command: {}", err);
+            command.env_remove(CHANGED_ENV)
+        }
+    }
+}
+
+/// A step of the watcher, the command of a route or a parallel command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepRef {
+    Step(usize),
+    Route(usize),
+    Parallel(usize),
+}
+
+/// Steps being executed after a change.
+#[derive(Debug)]
+struct Run {
+    plan: Vec<StepRef>,
+    /// Position of the step being executed in the plan.
+    position: usize,
+    started: Instant,
+    /// Step being executed, `None` once the steps are finished.
+    task: Option<Task>,
+    /// Whether the finished steps and parallel commands succeeded.
+    success: bool,
+    /// Parallel commands still being executed.
+    parallel: Vec<(StepRef, Task)>,
+}
+
+impl Run {
+    /// Stop the step and the parallel commands being executed.
+    fn kill(&mut self) {
+        for task in self
+            .task
+            .iter_mut()
+            .chain(self.parallel.iter_mut().map(|(_, x)| x))
+        {
+            task.kill();
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Task {
+    Child(process::Child),
+    Function(Option<thread::JoinHandle<Result<()>>>),
+    /// The command could not be started.
+    Failed,
+}
+
+impl Task {
+    /// Whether the task succeeded, once it finished.
+    fn finished(&mut self) -> Option<bool> {
+        match self {
+            Task::Child(child) => match child.try_wait() {
+                Ok(status) => status.map(|x| x.success()),
+                Err(err) => {
+                    log::error!("could not wait for the command: {}", err);
+                    Some(false)
+                }
+            },
+            Task::Function(handle) => match handle.take() {
+                Some(x) if x.is_finished() => Some(join(x)),
+                Some(x) => {
+                    *handle = Some(x);
+                    None
+                }
+                None => Some(false),
+            },
+            Task::Failed => Some(false),
+        }
+    }
+
+    /// Stop the command and wait for it to exit, or wait for the function to
+    /// return as it can't be interrupted.
+    fn kill(&mut self) {
+        match self {
+            Task::Child(child) => {
+                // NOTE: the processes started by the command are killed too
+                #[cfg(unix)]
+                process_group::kill(child.id());
+                #[cfg(windows)]
+                let _ = process::Command::new("taskkill")
+                    .args(["/T", "/F", "/PID", &child.id().to_string()])
+                    .stdout(process::Stdio::null())
+                    .stderr(process::Stdio::null())
+                    .status();
+                if let Err(err) = child.kill() {
+                    log::debug!("could not kill the command: {}", err);
+                }
+                let _ = child.wait();
+            }
+            Task::Function(handle) => {
+                if let Some(handle) = handle.take() {
+                    log::info!("Waiting for the function to return");
+                    join(handle);
+                }
+            }
+            Task::Failed => {}
+        }
+    }
+}
+
+/// Delay before the next run after the given number of failures in a row,
+/// doubling from 1 second after the second failure.
+fn backoff(failures: u32) -> Duration {
+    match failures {
+        0 | 1 => Duration::ZERO,
+        n => (Duration::from_secs(1) * 2u32.saturating_pow(n - 2)).min(MAX_BACKOFF),
+    }
+}
+
+/// Wait for the thread of a function, returning whether it succeeded.
+fn join(handle: thread::JoinHandle<Result<()>>) -> bool {
+    match handle.join() {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            log::error!("{:#}", err);
+            false
+        }
+        Err(_) => {
+            log::error!("the function panicked");
+            false
+        }
+    }
+}
+
+/// Clear the screen and the scrollback of the terminal.
+fn clear_terminal() {
+    #[cfg(unix)]
+    // SAFETY: `isatty` only inspects the file descriptor
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } != 1 {
+        return;
+    }
+
+    let mut stdout = io::stdout();
+    let _ = stdout
+        .write_all(b"\x1b[2J\x1b[3J\x1b[H")
+        .and_then(|()| stdout.flush());
+}
+
+/// Path with `/` separators, as matched by the glob patterns.
+fn relative_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|x| match x {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether a path or one of its parent directories matches one of the glob
+/// patterns.
+fn matches_globs(patterns: &[String], path: &str) -> bool {
+    let mut prefixes = path
+        .match_indices('/')
+        .map(|(i, _)| &path[..i])
+        .chain(std::iter::once(path));
+    prefixes.any(|prefix| patterns.iter().any(|x| glob_match(x, prefix)))
+}
+
+/// Whether a relative path matches a glob pattern.
+///
+/// `*` matches any sequence of characters within a segment and `**` any number
+/// of segments. A pattern without `/` matches the file names at any depth.
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    if !pattern.contains('/') {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        return wildcard_match(pattern, name);
+    }
+
+    let pattern: Vec<_> = pattern.trim_start_matches('/').split('/').collect();
+    let path: Vec<_> = path.split('/').collect();
+    segments_match(&pattern, &path)
+}
+
+fn segments_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| segments_match(rest, &path[i..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path)) => wildcard_match(segment, name) && segments_match(rest, path),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watcher(root: &Path) -> Watcher {
+        Watcher {
+            root: root.to_path_buf(),
+            watch_paths: vec![root.to_path_buf()],
+            exclude_paths: vec![root.join("target")],
+            watch_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            use_gitignore: false,
+            watch_hidden: false,
+            extensions: Vec::new(),
+            filters: Vec::new(),
+            event_kinds: default_event_kinds(),
+            clear_terminal: false,
+            interactive: false,
+            on_result: Vec::new(),
+            desktop_notifications: false,
+            max_runs: None,
+            exit_on_success: false,
+            restart_on_exit: false,
+            env_file: None,
+            steps: Vec::new(),
+            routes: Vec::new(),
+            parallel: Vec::new(),
+            change_senders: Vec::new(),
+            trigger: WatchTrigger::default(),
+            context: WatchContext::default(),
+            failures: 0,
+            failed_at: None,
+            runs: 0,
+            #[cfg(unix)]
+            groups: Vec::new(),
+            env_keys: Vec::new(),
+            debounce: Duration::from_secs(2),
+            ignore_rules: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("**/*.md", "README.md"));
+        assert!(glob_match("**/*.md", "docs/guide/intro.md"));
+        assert!(!glob_match("**/*.md", "docs/guide/intro.rs"));
+        assert!(glob_match("*.md", "docs/intro.md"));
+        assert!(glob_match("crates/*/src/**", "crates/app/src/lib.rs"));
+        assert!(glob_match("crates/*/src/**", "crates/app/src/bin/main.rs"));
+        assert!(!glob_match("crates/*/src/**", "crates/app/tests/it.rs"));
+        assert!(!glob_match(
+            "crates/*/src/**",
+            "crates/app/nested/src/lib.rs"
+        ));
+        assert!(glob_match("/assets/", "assets"));
+    }
+
+    #[test]
+    fn watched_paths() {
+        let root = Path::new("/workspace");
+        let mut watcher = watcher(root);
+        assert!(watcher.is_watched(&root.join("src/lib.rs")));
+        assert!(!watcher.is_watched(&root.join("target/debug/app")));
+        assert!(!watcher.is_watched(&root.join(".git/index")));
+        assert!(!watcher.is_watched(Path::new("/elsewhere/lib.rs")));
+        assert!(!watcher.is_watched(&root.join(".env")));
+
+        watcher.watch_hidden = true;
+        assert!(watcher.is_watched(&root.join(".env")));
+        assert!(watcher.is_watched(&root.join("content/.drafts/post.md")));
+        assert!(!watcher.is_watched(&root.join(".git/index")));
+
+        watcher.watch_globs = vec!["crates/*/src/**".to_string(), "assets".to_string()];
+        watcher.exclude_globs = vec!["**/*.md".to_string()];
+        assert!(watcher.is_watched(&root.join("crates/app/src/lib.rs")));
+        assert!(watcher.is_watched(&root.join("assets/images/logo.png")));
+        assert!(!watcher.is_watched(&root.join("crates/app/src/README.md")));
+        assert!(!watcher.is_watched(&root.join("crates/app/build.rs")));
+
+        watcher.filters = vec![WatchFilter(Arc::new(|path: &Path| {
+            !path.ends_with("src/generated.rs")
+        }))];
+        assert!(watcher.is_watched(&root.join("crates/app/src/lib.rs")));
+        assert!(!watcher.is_watched(&root.join("crates/app/src/generated.rs")));
+    }
+
+    #[test]
+    fn event_kinds() {
+        use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind, RenameMode};
+
+        let kinds: Vec<_> = [
+            EventKind::Access(AccessKind::Any),
+            EventKind::Create(CreateKind::File),
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            EventKind::Other,
+        ]
+        .iter()
+        .map(WatchEventKind::of)
+        .collect();
+        assert_eq!(
+            kinds,
+            [
+                Some(WatchEventKind::Access),
+                Some(WatchEventKind::Create),
+                Some(WatchEventKind::Modify),
+                Some(WatchEventKind::Metadata),
+                Some(WatchEventKind::Rename),
+                None,
+            ]
+        );
+        assert!(!default_event_kinds().contains(&WatchEventKind::Metadata));
+    }
+
+    #[test]
+    fn extension_filter() {
+        let root = Path::new("/workspace");
+        let mut watcher = watcher(root);
+        watcher.extensions = vec!["rs".to_string(), ".scss".to_string()];
+        assert!(watcher.is_watched(&root.join("src/lib.rs")));
+        assert!(watcher.is_watched(&root.join("styles/main.scss")));
+        assert!(!watcher.is_watched(&root.join("data/app.sqlite")));
+        assert!(!watcher.is_watched(&root.join("LICENSE")));
+    }
+
+    #[test]
+    fn gitignore_rules() {
+        let rules =
+            parse_ignore_file("# comment\n/dist\nnode_modules/\n*.log\n!keep.log\n\\#notes\n");
+        assert_eq!(rules.len(), 5);
+        assert!(rules[0].anchored && rules[0].matches("dist", true));
+        assert!(!rules[0].matches("app/dist", true));
+        assert!(rules[1].matches("node_modules", true));
+        assert!(!rules[1].matches("node_modules", false));
+        assert!(rules[2].matches("logs/debug.log", false));
+        assert!(rules[3].negated && rules[3].matches("keep.log", false));
+        assert_eq!(rules[4].pattern, "#notes");
+    }
+
+    #[test]
+    fn gitignore_skips_ignored_paths() {
+        let root =
+            std::env::temp_dir().join(format!("xtask-wasm-gitignore-{}", std::process::id()));
+        fs::create_dir_all(root.join("app/node_modules/lib")).unwrap();
+        fs::write(root.join(".gitignore"), "node_modules/\n*.log\n").unwrap();
+        fs::write(root.join("app/.gitignore"), "!keep.log\ngenerated.rs\n").unwrap();
+
+        let mut watcher = watcher(&root);
+        assert!(watcher.is_watched(&root.join("app/generated.rs")));
+        watcher.use_gitignore = true;
+        assert!(!watcher.is_watched(&root.join("app/node_modules/lib/index.js")));
+        assert!(!watcher.is_watched(&root.join("app/debug.log")));
+        assert!(watcher.is_watched(&root.join("app/keep.log")));
+        assert!(!watcher.is_watched(&root.join("app/generated.rs")));
+        assert!(watcher.is_watched(&root.join("app/src/lib.rs")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_stop_at_first_failure() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-commands-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = watcher(&root);
+        for script in ["echo 1 >> out", "exit 1", "echo 3 >> out"] {
+            let mut command = process::Command::new("sh");
+            command.current_dir(&root).args(["-c", script]);
+            watcher.steps.push(Step::Command(command));
+        }
+
+        let mut run = watcher.start();
+        while watcher.poll(&mut run).is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fs::read_to_string(root.join("out")).unwrap(), "1\n");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parallel_commands() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-parallel-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = watcher(&root);
+        let mut command = process::Command::new("sh");
+        command
+            .current_dir(&root)
+            .args(["-c", "sleep 0.5 && echo step >> out"]);
+        watcher.steps.push(Step::Command(command));
+        let mut command = process::Command::new("sh");
+        command
+            .current_dir(&root)
+            .args(["-c", "echo parallel >> out && exit 1"]);
+        watcher.parallel.push(Step::Command(command));
+
+        let mut run = watcher.start();
+        let result = loop {
+            match watcher.poll(&mut run) {
+                Some(result) => break result,
+                None => std::thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        assert!(!result.success);
+        assert_eq!(
+            fs::read_to_string(root.join("out")).unwrap(),
+            "parallel\nstep\n"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn killing_stops_the_processes_of_the_command() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-group-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = watcher(&root);
+        let mut command = process::Command::new("sh");
+        command
+            .current_dir(&root)
+            .args(["-c", "sleep 30 & echo $! > pid; wait"]);
+        process_group::set(&mut command);
+        watcher.steps.push(Step::Command(command));
+
+        let mut run = watcher.start();
+        let pid = loop {
+            match fs::read_to_string(root.join("pid")) {
+                Ok(pid) if pid.ends_with('\n') => break pid.trim().to_string(),
+                _ => std::thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        watcher.kill(&mut run);
+        // NOTE: the process can stay a zombie until it's reaped by init
+        std::thread::sleep(Duration::from_millis(100));
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+        assert!(stat.is_empty() || stat.contains(") Z "), "{}", stat);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn function_gets_the_context() {
+        let iterations = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut watcher = watcher(Path::new("/workspace"));
+        watcher.steps.push(Step::Function(RunWith(Arc::new({
+            let iterations = iterations.clone();
+            move |context: &WatchContext| {
+                iterations.lock().unwrap().push(context.iteration);
+                crate::anyhow::ensure!(context.changed_paths.is_empty(), "changed");
+                Ok(())
+            }
+        }))));
+
+        let mut run = watcher.start();
+        while watcher.poll(&mut run).is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        watcher.context = WatchContext {
+            changed_paths: vec![PathBuf::from("/workspace/src/lib.rs")],
+            iteration: 1,
+        };
+        let mut run = watcher.start().unwrap();
+        run.kill();
+        assert_eq!(run.task.as_mut().and_then(Task::finished), Some(false));
+        assert_eq!(*iterations.lock().unwrap(), [0, 1]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_get_the_changed_paths() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-changed-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = watcher(&root);
+        let mut command = process::Command::new("sh");
+        command
+            .current_dir(&root)
+            .args(["-c", &format!("printf %s \"${}\" > out", CHANGED_ENV)]);
+        watcher.steps.push(Step::Command(command));
+        watcher.context = WatchContext {
+            changed_paths: vec![root.join("a.scss"), root.join("b.scss")],
+            iteration: 1,
+        };
+
+        let mut run = watcher.start();
+        while watcher.poll(&mut run).is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            fs::read_to_string(root.join("out")).unwrap(),
+            format!(
+                "{}:{}",
+                root.join("a.scss").display(),
+                root.join("b.scss").display()
+            )
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn routes_of_the_changed_paths() {
+        let root = Path::new("/workspace");
+        let mut watcher = watcher(root);
+        for _ in 0..2 {
+            watcher
+                .steps
+                .push(Step::Command(process::Command::new("true")));
+        }
+        for pattern in ["**/*.scss", "assets"] {
+            watcher.routes.push((
+                pattern.to_string(),
+                Step::Command(process::Command::new("true")),
+            ));
+        }
+        watcher
+            .parallel
+            .push(Step::Command(process::Command::new("true")));
+
+        assert_eq!(
+            watcher.plan(&[]),
+            [StepRef::Step(0), StepRef::Step(1), StepRef::Parallel(0)]
+        );
+        assert_eq!(
+            watcher.plan(&[root.join("styles/main.scss"), root.join("assets/app.scss")]),
+            [StepRef::Route(0)]
+        );
+        assert_eq!(
+            watcher.plan(&[root.join("assets/logo.png"), root.join("src/lib.rs")]),
+            [
+                StepRef::Route(1),
+                StepRef::Step(0),
+                StepRef::Step(1),
+                StepRef::Parallel(0)
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_delay_the_next_runs() {
+        assert_eq!(backoff(1), Duration::ZERO);
+        assert_eq!(backoff(2), Duration::from_secs(1));
+        assert_eq!(backoff(4), Duration::from_secs(4));
+        assert_eq!(backoff(40), MAX_BACKOFF);
+
+        let mut watcher = watcher(Path::new("/workspace"));
+        watcher
+            .steps
+            .push(Step::Command(process::Command::new("/nonexistent/command")));
+        for _ in 0..2 {
+            let mut run = watcher.start();
+            let result = watcher.poll(&mut run).unwrap();
+            watcher.finish(&result);
+        }
+        assert_eq!(watcher.failures, 2);
+        assert!(!watcher.backoff_elapsed());
+        watcher.finish(&WatchResult {
+            success: true,
+            duration: Duration::ZERO,
+            iteration: 2,
+        });
+        assert!(watcher.backoff_elapsed());
+    }
+
+    #[test]
+    fn keys() {
+        assert!(matches!(Message::of_key("r\n"), Some(Message::Run)));
+        assert!(matches!(Message::of_key(" p"), Some(Message::Pause)));
+        assert!(matches!(Message::of_key("q"), Some(Message::Quit)));
+        assert!(Message::of_key("x").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn results_are_reported() {
+        let results = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut watcher = watcher(Path::new("/workspace"));
+        watcher.on_result.push(OnResult(Arc::new({
+            let results = results.clone();
+            move |result: &WatchResult| results.lock().unwrap().push(result.success)
+        })));
+        for program in ["true", "false"] {
+            watcher.steps = vec![Step::Command(process::Command::new(program))];
+            let mut run = watcher.start();
+            let result = loop {
+                match watcher.poll(&mut run) {
+                    Some(result) => break result,
+                    None => std::thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            watcher.finish(&result);
+        }
+        assert_eq!(*results.lock().unwrap(), [true, false]);
+    }
+
+    #[test]
+    fn runs_are_limited() {
+        let result = |success| WatchResult {
+            success,
+            duration: Duration::ZERO,
+            iteration: 0,
+        };
+        let mut limited = watcher(Path::new("/workspace"));
+        limited.max_runs = Some(2);
+        assert!(!limited.finish(&result(true)));
+        assert!(limited.finish(&result(false)));
+
+        let mut until_success = watcher(Path::new("/workspace"));
+        until_success.exit_on_success = true;
+        assert!(!until_success.finish(&result(false)));
+        assert!(until_success.finish(&result(true)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stopping_kills_the_command() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-stop-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = watcher(&root);
+        let mut command = process::Command::new("sleep");
+        command.arg("30");
+        watcher.steps = vec![Step::Command(command)];
+
+        let started = Instant::now();
+        let handle = watcher.spawn(|| panic!("quit")).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        handle.stop();
+        handle.join();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn env_files() {
+        let content = "# comment\nexport API_URL=http://localhost:3000 # local\n\n\
+            NAME = \"the app\"\nQUOTED='a # b'\nMULTILINE=\"a\\nb\"\ninvalid\n";
+        assert_eq!(
+            parse_env_file(content),
+            [
+                ("API_URL".to_string(), "http://localhost:3000".to_string()),
+                ("NAME".to_string(), "the app".to_string()),
+                ("QUOTED".to_string(), "a # b".to_string()),
+                ("MULTILINE".to_string(), "a\nb".to_string()),
+            ]
+        );
+
+        let mut watcher = watcher(Path::new("/workspace"));
+        watcher.steps = vec![Step::Command(process::Command::new("true"))];
+        watcher.set_env(vec![
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "2".to_string()),
+        ]);
+        watcher.set_env(vec![("A".to_string(), "3".to_string())]);
+        let envs: Vec<_> = match &watcher.steps[0] {
+            Step::Command(command) => command.get_envs().collect(),
+            Step::Function(_) => unreachable!(),
+        };
+        assert_eq!(
+            envs,
+            [("A".as_ref(), Some("3".as_ref())), ("B".as_ref(), None)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exited_commands_are_restarted() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-restart-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = watcher(&root);
+        watcher.debounce = Duration::from_millis(100);
+        watcher.restart_on_exit = true;
+        watcher.steps = vec![Step::Command(process::Command::new("false"))];
+        let (sender, receiver) = mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        watcher
+            .on_result
+            .push(OnResult(Arc::new(move |result: &WatchResult| {
+                let _ = sender.lock().unwrap().send(result.iteration);
+            })));
+
+        let handle = watcher.spawn(|| panic!("quit")).unwrap();
+        for iteration in 0..3 {
+            assert_eq!(
+                receiver.recv_timeout(Duration::from_secs(10)),
+                Ok(iteration)
+            );
+        }
+        handle.stop();
+        handle.join();
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn triggers_run_the_command() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-trigger-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut watcher = watcher(&root);
+        watcher.steps = vec![Step::Command(process::Command::new("true"))];
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        watcher
+            .on_result
+            .push(OnResult(Arc::new(move |result: &WatchResult| {
+                let _ = sender.lock().unwrap().send(result.iteration);
+            })));
+        let trigger = watcher.trigger.clone();
+        assert!(!trigger.trigger());
+
+        let handle = watcher.spawn(|| panic!("quit")).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(0));
+        assert!(trigger.trigger());
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(1));
+        handle.stop();
+        handle.join();
+        assert!(!trigger.trigger());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn changes_are_sent() {
+        let root = std::env::temp_dir().join(format!("xtask-wasm-events-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let mut watcher = watcher(&root);
+        watcher.debounce = Duration::from_millis(100);
+        let (sender, receiver) = mpsc::channel();
+        watcher.change_senders.push(sender);
+
+        let handle = watcher.spawn(|| panic!("quit")).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        fs::write(root.join("lib.rs"), "").unwrap();
+        let context = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(context.changed_paths, [root.join("lib.rs")]);
+        assert_eq!(context.iteration, 1);
+        handle.stop();
+        handle.join();
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -37,6 +37,9 @@ use syn::{parse, parse_macro_input};
 ///     cargo run --example my_example
 ///     ```
 ///
+///   The development server looks for a free port if the default one is
+///   already in use.
+///
 /// ## Arguments
 ///
 /// You can give arguments to the macro to customize the example:
@@ -170,7 +173,8 @@ impl RunExample {
                             .example(module_path!())
                             #app_name
                             #static_dir
-                            .run(env!("CARGO_PKG_NAME"))?;
+                            .run(env!("CARGO_PKG_NAME"))?
+                            .dist_dir;
 
                         #index
 
@@ -178,12 +182,12 @@ impl RunExample {
                     }
                     Some(Command::Start(dev_server)) => {
                         let served_path = xtask_wasm::default_dist_dir(false);
-                        dev_server.command(dist_command).start(served_path)
+                        dev_server.auto_port(true).command(dist_command).start(served_path)
                     }
                     None => {
                         let dev_server: xtask_wasm::DevServer = clap::Parser::parse();
                         let served_path = xtask_wasm::default_dist_dir(false);
-                        dev_server.command(dist_command).start(served_path)
+                        dev_server.auto_port(true).command(dist_command).start(served_path)
                     }
                 }
             }